@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sim86::decode;
+
+// Decoding arbitrary bytes must never panic, every instruction has to consume between one and seven
+// bytes, a segment override prefix on top of the longest instructions making 7, and together they
+// have to cover the whole input.
+fuzz_target!(|data: &[u8]| {
+    let mut memory = data;
+    while !memory.is_empty() {
+        let left = memory.len();
+        let (offset, instruction) = decode::single_instruction(&mut memory);
+        assert!((1..=7).contains(&offset));
+        assert_eq!(left - memory.len(), offset);
+        let _ = instruction.to_string();
+    }
+});
@@ -0,0 +1,201 @@
+//! Control flow graph extraction from decoded code.
+//!
+//! The code is split into basic blocks: runs of instructions that are only ever entered at the
+//! top and left at the bottom. Blocks are connected by the jumps, loops and calls that end them
+//! and by falling through into the block that follows, unless they end in an unconditional jump,
+//! a return or `hlt`.
+
+use std::fmt::Write;
+
+use crate::{decode, Branch, ImpliedOp, Instruction};
+
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    /// Address of the first instruction in the block.
+    pub start: usize,
+    /// Address one past the last byte of the block.
+    pub end: usize,
+    pub instructions: Vec<(usize, Instruction)>,
+}
+
+impl BasicBlock {
+    pub fn last(&self) -> Option<&Instruction> {
+        self.instructions.last().map(|(_, instruction)| instruction)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// The jump or loop at the end of the block was taken.
+    Taken,
+    /// Execution continued into the next block in memory.
+    FallThrough,
+    /// The call at the end of the block entered a procedure.
+    Call,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    /// Index of the block the edge leaves from.
+    pub from: usize,
+    /// Index of the block the edge leads to.
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+    pub edges: Vec<Edge>,
+}
+
+impl ControlFlowGraph {
+    pub fn from_code(code: &[u8]) -> Self {
+        let instructions = decode_with_lengths(code);
+
+        // Every branch target and every instruction following a transfer of control starts a new
+        // block.
+        let mut leaders = vec![0];
+        for &(address, length, instruction) in &instructions {
+            if let Some(target) = branch_target(address + length, &instruction) {
+                leaders.push(target);
+            }
+            if ends_block(&instruction) {
+                leaders.push(address + length);
+            }
+        }
+        // Targets in the middle of an instruction or outside of the code can't start a block.
+        leaders.retain(|leader| {
+            instructions
+                .binary_search_by_key(leader, |(address, ..)| *address)
+                .is_ok()
+        });
+        leaders.sort_unstable();
+        leaders.dedup();
+
+        let mut blocks: Vec<BasicBlock> = Vec::with_capacity(leaders.len());
+        for (address, length, instruction) in instructions {
+            if leaders.binary_search(&address).is_ok() {
+                blocks.push(BasicBlock {
+                    start: address,
+                    end: address,
+                    instructions: Vec::new(),
+                });
+            }
+            let block = blocks
+                .last_mut()
+                .expect("the first instruction is a leader");
+            block.end = address + length;
+            block.instructions.push((address, instruction));
+        }
+
+        let mut graph = Self {
+            blocks,
+            edges: Vec::new(),
+        };
+        for from in 0..graph.blocks.len() {
+            let block = &graph.blocks[from];
+            let Some(&last) = block.last() else {
+                continue;
+            };
+            if let Some(to) = branch_target(block.end, &last).and_then(|t| graph.block_at(t)) {
+                let kind = match last {
+                    Instruction::Call(_) => EdgeKind::Call,
+                    _ => EdgeKind::Taken,
+                };
+                graph.edges.push(Edge { from, to, kind });
+            }
+            if falls_through(&last) && from + 1 < graph.blocks.len() {
+                graph.edges.push(Edge {
+                    from,
+                    to: from + 1,
+                    kind: EdgeKind::FallThrough,
+                });
+            }
+        }
+        graph
+    }
+
+    /// Index of the block starting at `address`.
+    pub fn block_at(&self, address: usize) -> Option<usize> {
+        self.blocks
+            .binary_search_by_key(&address, |block| block.start)
+            .ok()
+    }
+
+    pub fn successors(&self, block: usize) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |edge| edge.from == block)
+    }
+
+    /// Renders the graph in the Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box fontname=\"monospace\"];\n");
+        for (index, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for (address, instruction) in &block.instructions {
+                let text = match instruction {
+                    Instruction::Unrecognized(byte) => format!("unrecognized {byte:#04x}"),
+                    _ => instruction.to_string(),
+                };
+                write!(label, "{address:04x}: {}\\l", text.replace('"', "\\\""))
+                    .expect("can write into a string");
+            }
+            writeln!(dot, "    b{index} [label=\"{label}\"];").expect("can write into a string");
+        }
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::Taken => "label=\"taken\"",
+                EdgeKind::FallThrough => "style=dashed",
+                EdgeKind::Call => "label=\"call\" style=bold",
+            };
+            writeln!(dot, "    b{} -> b{} [{style}];", edge.from, edge.to)
+                .expect("can write into a string");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn decode_with_lengths(code: &[u8]) -> Vec<(usize, usize, Instruction)> {
+    let mut instructions = Vec::new();
+    let mut address = 0;
+    while address < code.len() {
+        let (length, instruction) = decode::single_instruction(&mut &code[address..]);
+        instructions.push((address, length, instruction));
+        address += length;
+    }
+    instructions
+}
+
+/// Address a jump, loop or call transfers control to when taken, given the address right after
+/// it. Indirect and far targets aren't known from the code alone.
+fn branch_target(next: usize, instruction: &Instruction) -> Option<usize> {
+    match instruction {
+        Instruction::Jump { offset, .. } | Instruction::Loop { offset, .. } => {
+            next.checked_add_signed((*offset).into())
+        }
+        Instruction::Jmp(Branch::Short(offset)) => next.checked_add_signed((*offset).into()),
+        Instruction::Jmp(Branch::Near(offset)) | Instruction::Call(Branch::Near(offset)) => {
+            next.checked_add_signed((*offset).into())
+        }
+        _ => None,
+    }
+}
+
+/// Whether `instruction` may transfer control elsewhere, so that the one after it starts a block.
+fn ends_block(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Jump { .. } | Instruction::Loop { .. } | Instruction::Call(_)
+    ) || !falls_through(instruction)
+}
+
+/// Whether execution can continue with the instruction after `instruction`.
+fn falls_through(instruction: &Instruction) -> bool {
+    !matches!(
+        instruction,
+        Instruction::Jmp(_)
+            | Instruction::Return { .. }
+            | Instruction::Implied(ImpliedOp::Iret | ImpliedOp::Hlt)
+    )
+}
@@ -0,0 +1,252 @@
+use std::path::{Path, PathBuf};
+
+use sim86::{
+    format::{Case, Radix, Style, Syntax},
+    prefetch::Cpu,
+    watch::{WatchAction, Watchpoint},
+};
+
+pub const HELP: &str = "\
+Intel 8086 disassembler and simulator
+
+Usage: sim86 <command> [options] <files>...
+
+Commands:
+  disasm    Print the disassembly of every file
+  exec      Simulate every file, printing a trace and the final registers
+  debug     Simulate every file one instruction at a time, reading commands from stdin
+  tui       Like debug, but redraw a full screen view of code, registers, stack and memory
+  cfg       Print the control flow graph of every file in the Graphviz DOT format
+  stats     Print the static instruction mix of every file: the number of instructions and their
+            bytes per mnemonic, addressing mode and operand width
+
+Options:
+  -o, --output-dir <dir>    Directory memory dumps are written into [default: .]
+  -d, --dump                Dump the memory of every simulated file once it finishes
+      --dump-name <name>    File name of memory dumps, where {name} is replaced by the input file
+                            name without its extension and {index} by its position on the
+                            command line [default: {name}.data]
+  -l, --listing <path>      NASM listing (`nasm -l`) of every file, whose source lines and labels
+                            the trace shows instead of the disassembly. {name} and {index} are
+                            replaced like in --dump-name
+  -i, --init <path>         Setup file with the registers, flags and data in memory to start every
+                            simulation with, see below. {name} and {index} are replaced like in
+                            --dump-name
+  -s, --stats               Print execution statistics after simulating
+  -c, --cycles              Estimate the clocks taken, implies --stats
+  -t, --timing <cpu>        Model the prefetch queue and bus of an 8086 or 8088 for detailed
+                            clock counts, implies --cycles
+      --wait-states <n>     Clocks added to every bus cycle by --timing [default: 0]
+  -q, --quiet               Don't print the execution trace
+      --devices             Attach a console on port 0xE9 and a timer on ports 0x40-0x43
+  -w, --watch <range>       Log the accesses to a hexadecimal address range, given as `3e8` or
+                            `3e8-3ef`, optionally followed by `:r` or `:w` to only watch reads
+                            or writes
+  -b, --break <range>       Like --watch, but also stop the simulation after the access
+      --warn-code-writes    Warn about instructions writing into code that already executed or
+                            that the prefetch queue would hold already
+      --syntax <syntax>     Assembler syntax of the disassembly: nasm, intel or att [default: nasm]
+      --case <case>         Letter case of mnemonics and registers: lower or upper
+  -x, --hex                 Write numbers in the disassembly in hexadecimal
+  -h, --help                Print this help
+
+Setup files are written in a subset of TOML:
+  [registers]
+  si = 0x1000               any register by name, as well as ip
+  flags = \"CZ\"              the flags to set, out of CPAZSO
+  [[load]]
+  address = 0x1000
+  file = \"input.bin\"        relative to the setup file, or `bytes = [1, 2, 3]`
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Disasm,
+    Exec,
+    Debug,
+    Tui,
+    Cfg,
+    Stats,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub command: Command,
+    pub files: Vec<String>,
+    pub output_dir: PathBuf,
+    pub dump: bool,
+    pub dump_name: String,
+    pub listing: Option<String>,
+    pub init: Option<String>,
+    pub stats: bool,
+    pub cycles: bool,
+    pub timing: Option<Cpu>,
+    pub wait_states: u32,
+    pub quiet: bool,
+    pub devices: bool,
+    pub watchpoints: Vec<Watchpoint>,
+    pub warn_code_writes: bool,
+    pub style: Style,
+}
+
+impl Options {
+    /// Parses the command line arguments, not including the program name. Returns `Ok(None)` when
+    /// help was requested.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Option<Self>, String> {
+        let command = match args.next().as_deref() {
+            Some("disasm") => Command::Disasm,
+            Some("exec") => Command::Exec,
+            Some("debug") => Command::Debug,
+            Some("tui") => Command::Tui,
+            Some("cfg") => Command::Cfg,
+            Some("stats") => Command::Stats,
+            Some("-h" | "--help") => return Ok(None),
+            Some(command) => return Err(format!("unknown command `{command}`")),
+            None => return Err("no command provided".to_owned()),
+        };
+
+        let mut options = Self {
+            command,
+            files: Vec::new(),
+            output_dir: PathBuf::from("."),
+            dump: false,
+            dump_name: "{name}.data".to_owned(),
+            listing: None,
+            init: None,
+            stats: false,
+            cycles: false,
+            timing: None,
+            wait_states: 0,
+            quiet: false,
+            devices: false,
+            watchpoints: Vec::new(),
+            warn_code_writes: false,
+            style: Style::default(),
+        };
+        while let Some(arg) = args.next() {
+            let mut value = |option: &str| {
+                args.next()
+                    .ok_or_else(|| format!("`{option}` expects a value"))
+            };
+            match arg.as_str() {
+                "-o" | "--output-dir" => options.output_dir = PathBuf::from(value(&arg)?),
+                "-d" | "--dump" => options.dump = true,
+                "--dump-name" => options.dump_name = value(&arg)?,
+                "-l" | "--listing" => options.listing = Some(value(&arg)?),
+                "-i" | "--init" => options.init = Some(value(&arg)?),
+                "-s" | "--stats" => options.stats = true,
+                "-c" | "--cycles" => {
+                    options.stats = true;
+                    options.cycles = true;
+                }
+                "-t" | "--timing" => {
+                    options.stats = true;
+                    options.cycles = true;
+                    options.timing = match value(&arg)?.as_str() {
+                        "8086" => Some(Cpu::I8086),
+                        "8088" => Some(Cpu::I8088),
+                        cpu => return Err(format!("unknown cpu `{cpu}`, expected 8086 or 8088")),
+                    }
+                }
+                "--wait-states" => {
+                    let wait_states = value(&arg)?;
+                    options.wait_states = wait_states
+                        .parse()
+                        .map_err(|_| format!("invalid number of wait states `{wait_states}`"))?;
+                }
+                "-q" | "--quiet" => options.quiet = true,
+                "--devices" => options.devices = true,
+                "-w" | "--watch" => options
+                    .watchpoints
+                    .push(parse_watchpoint(&value(&arg)?, WatchAction::Log)?),
+                "-b" | "--break" => options
+                    .watchpoints
+                    .push(parse_watchpoint(&value(&arg)?, WatchAction::Break)?),
+                "--warn-code-writes" => options.warn_code_writes = true,
+                "--syntax" => {
+                    options.style.syntax = match value(&arg)?.as_str() {
+                        "nasm" => Syntax::Nasm,
+                        "intel" | "masm" => Syntax::Intel,
+                        "att" | "gas" => Syntax::Att,
+                        syntax => return Err(format!("unknown syntax `{syntax}`")),
+                    }
+                }
+                "--case" => {
+                    let case = match value(&arg)?.as_str() {
+                        "lower" => Case::Lower,
+                        "upper" => Case::Upper,
+                        case => return Err(format!("unknown case `{case}`")),
+                    };
+                    options.style.mnemonics = case;
+                    options.style.registers = case;
+                }
+                "-x" | "--hex" => options.style.radix = Radix::Hexadecimal,
+                "-h" | "--help" => return Ok(None),
+                "--" => options.files.extend(args.by_ref()),
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{arg}`")),
+                _ => options.files.push(arg),
+            }
+        }
+
+        if options.files.is_empty() {
+            return Err("no input files provided".to_owned());
+        }
+        Ok(Some(options))
+    }
+
+    /// Where the memory dump of the `index`th input `file` goes.
+    pub fn dump_path(&self, file: &str, index: usize) -> PathBuf {
+        self.output_dir
+            .join(substitute(&self.dump_name, file, index))
+    }
+
+    /// Where the listing of the `index`th input `file` is, if one was given.
+    pub fn listing_path(&self, file: &str, index: usize) -> Option<PathBuf> {
+        let listing = self.listing.as_ref()?;
+        Some(PathBuf::from(substitute(listing, file, index)))
+    }
+
+    /// Where the setup file of the `index`th input `file` is, if one was given.
+    pub fn init_path(&self, file: &str, index: usize) -> Option<PathBuf> {
+        let init = self.init.as_ref()?;
+        Some(PathBuf::from(substitute(init, file, index)))
+    }
+}
+
+/// Replaces `{name}` in `template` by the name of `file` without its extension and `{index}` by
+/// its position on the command line.
+fn substitute(template: &str, file: &str, index: usize) -> String {
+    let name = Path::new(file)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    template
+        .replace("{name}", &name)
+        .replace("{index}", &index.to_string())
+}
+
+/// Parses a watchpoint given as `start[-end][:r|:w]`, with hexadecimal addresses.
+fn parse_watchpoint(spec: &str, action: WatchAction) -> Result<Watchpoint, String> {
+    let (range, access) = spec.split_once(':').unwrap_or((spec, "rw"));
+    let (reads, writes) = match access {
+        "r" => (true, false),
+        "w" => (false, true),
+        "rw" => (true, true),
+        _ => return Err(format!("unknown access `{access}` in watchpoint `{spec}`")),
+    };
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let address = |text: &str| {
+        usize::from_str_radix(text.trim_start_matches("0x"), 16)
+            .map_err(|_| format!("invalid address `{text}` in watchpoint `{spec}`"))
+    };
+    let (start, end) = (address(start)?, address(end)?);
+    if end < start {
+        return Err(format!("empty range in watchpoint `{spec}`"));
+    }
+    Ok(Watchpoint {
+        range: start..=end,
+        reads,
+        writes,
+        action,
+    })
+}
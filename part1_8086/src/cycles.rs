@@ -0,0 +1,164 @@
+//! Clock estimates for the 8086, following the instruction timing tables of the 8086 manual.
+//!
+//! The estimates cover the execution unit only. Instruction fetch and wait states are ignored,
+//! except for the 4 clock penalty of every word transferred from or to an odd address, which
+//! the caller adds on top since it depends on the addresses actually used. The
+//! [`prefetch`](crate::prefetch) model accounts for the rest.
+
+use crate::{
+    AddressBase, ArithOp, Branch, EffectiveAdress, ImpliedOp, Instruction, LoadOp, LoopKind, Place,
+    Port, RegisterByte, RegisterWord, UnaryOp,
+};
+
+/// Extra clocks for every word transferred from or to an odd address.
+pub const UNALIGNED_WORD_PENALTY: u32 = 4;
+
+/// Clocks spent computing an effective address, including 2 for a segment override prefix.
+pub fn effective_address(address: &EffectiveAdress) -> u32 {
+    use AddressBase::*;
+    let has_displacement = address.displacement_width().is_some();
+    let clocks = match (address.base(), has_displacement) {
+        // direct address
+        (None, _) => 6,
+        // base or index only
+        (Some(Si | Di | Bp | Bx), false) => 5,
+        (Some(Si | Di | Bp | Bx), true) => 9,
+        // BP + DI, BX + SI
+        (Some(BpDi | BxSi), false) => 7,
+        (Some(BpDi | BxSi), true) => 11,
+        // BP + SI, BX + DI
+        (Some(BpSi | BxDi), false) => 8,
+        (Some(BpSi | BxDi), true) => 12,
+    };
+    match address.segment() {
+        Some(_) => clocks + 2,
+        None => clocks,
+    }
+}
+
+/// Clocks taken by `instruction`. `taken` tells whether a jump or loop transferred control and
+/// `shift_count` is the value of CL at the time a shift by CL executed.
+pub fn estimate(instruction: &Instruction, taken: bool, shift_count: u8) -> Option<u32> {
+    let ea = |place: &Place| match place {
+        Place::Adress(address) => Some(effective_address(address)),
+        _ => None,
+    };
+
+    let clocks = match instruction {
+        Instruction::Arithmetic { op, target, source } => match (ea(target), ea(source)) {
+            (None, None) => 3,
+            (None, Some(ea)) => 9 + ea,
+            (Some(ea), _) if *op == ArithOp::Cmp => 9 + ea,
+            (Some(ea), _) => 16 + ea,
+        },
+        Instruction::ArithmeticImmediate { op, target, .. } => match ea(target) {
+            None => 4,
+            Some(ea) if *op == ArithOp::Cmp => 10 + ea,
+            Some(ea) => 17 + ea,
+        },
+        Instruction::Jump { .. } => {
+            if taken {
+                16
+            } else {
+                4
+            }
+        }
+        Instruction::Loop { kind, .. } => match (kind, taken) {
+            (LoopKind::Loop, true) => 17,
+            (LoopKind::Loop, false) => 5,
+            (LoopKind::Loopz, true) => 18,
+            (LoopKind::Loopz, false) => 6,
+            (LoopKind::Loopnz, true) => 19,
+            (LoopKind::Loopnz, false) => 5,
+            (LoopKind::Jcxz, true) => 18,
+            (LoopKind::Jcxz, false) => 6,
+        },
+        Instruction::Shift { target, by_cl, .. } => {
+            let per_bit = 4 * shift_count as u32;
+            match (ea(target), by_cl) {
+                (None, false) => 2,
+                (None, true) => 8 + per_bit,
+                (Some(ea), false) => 15 + ea,
+                (Some(ea), true) => 20 + ea + per_bit,
+            }
+        }
+        Instruction::Test { target, source } => match ea(target).or(ea(source)) {
+            None => 3,
+            Some(ea) => 9 + ea,
+        },
+        Instruction::TestImmediate { target, .. } => match target {
+            Place::Byte(RegisterByte::AL) | Place::Word(RegisterWord::AX) => 4,
+            Place::Adress(address) => 11 + effective_address(address),
+            _ => 5,
+        },
+        Instruction::Unary { op, target, .. } => match (op, target) {
+            (UnaryOp::Mul | UnaryOp::Imul | UnaryOp::Div | UnaryOp::Idiv, _) => return None,
+            (UnaryOp::Inc | UnaryOp::Dec, Place::Word(_)) => 2,
+            (UnaryOp::Inc | UnaryOp::Dec, Place::Adress(address)) => {
+                15 + effective_address(address)
+            }
+            (_, Place::Adress(address)) => 16 + effective_address(address),
+            _ => 3,
+        },
+        Instruction::In { port, .. } | Instruction::Out { port, .. } => match port {
+            Port::Immediate(_) => 10,
+            Port::Dx => 8,
+        },
+        Instruction::Mov { target, source } => match (ea(target), ea(source)) {
+            // assemblers always pick the short accumulator form for direct addresses
+            _ if is_accumulator_direct(target, source) => 10,
+            (None, None) => 2,
+            (None, Some(ea)) => 8 + ea,
+            (Some(ea), _) => 9 + ea,
+        },
+        Instruction::MovImmediate { target, .. } => match ea(target) {
+            None => 4,
+            Some(ea) => 10 + ea,
+        },
+        Instruction::Xchg { target, source } => match (target, ea(source)) {
+            (_, Some(ea)) => 17 + ea,
+            // assemblers pick the one byte form whenever AX is involved
+            _ if [target, source].contains(&&Place::Word(RegisterWord::AX)) => 3,
+            _ => 4,
+        },
+        Instruction::Load { op, source, .. } => match (op, ea(source)) {
+            (LoadOp::Lea, Some(ea)) => 2 + ea,
+            (_, Some(ea)) => 16 + ea,
+            (_, None) => return None,
+        },
+        Instruction::Call(Branch::Far { .. }) => 28,
+        Instruction::Jmp(Branch::Far { .. }) => 15,
+        Instruction::Call(Branch::FarIndirect(Place::Adress(address))) => {
+            37 + effective_address(address)
+        }
+        Instruction::Jmp(Branch::FarIndirect(Place::Adress(address))) => {
+            24 + effective_address(address)
+        }
+        Instruction::Return { far: true, pop } => match pop {
+            None => 18,
+            Some(_) => 17,
+        },
+        Instruction::Implied(op) => match op {
+            ImpliedOp::Xlat => 11,
+            ImpliedOp::Lahf | ImpliedOp::Sahf => 4,
+            ImpliedOp::Cbw => 2,
+            ImpliedOp::Cwd => 5,
+            ImpliedOp::Iret => 24,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(clocks)
+}
+
+/// Whether a `mov` between `target` and `source` fits the accumulator to or from memory form.
+fn is_accumulator_direct(target: &Place, source: &Place) -> bool {
+    let is_accumulator = |place| {
+        matches!(
+            place,
+            &Place::Byte(RegisterByte::AL) | &Place::Word(RegisterWord::AX)
+        )
+    };
+    let is_direct = |place| matches!(place, &Place::Adress(address) if address.is_direct());
+    is_accumulator(target) && is_direct(source) || is_direct(target) && is_accumulator(source)
+}
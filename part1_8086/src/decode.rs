@@ -1,11 +1,26 @@
 use std::fmt::Write;
 
-use crate::{Instruction, Mode, Place, RegisterByte, RegisterWord};
+use crate::{
+    format::Style, ArithOp, Branch, ImpliedOp, Instruction, JumpCondition, LoadOp, LoopKind, Mode,
+    Place, Port, RegisterByte, RegisterWord, Repeat, SegmentRegister, ShiftOp, StringOp, UnaryOp,
+    Width,
+};
 
 #[inline]
-pub fn all_instructions(mut memory: &[u8]) -> String {
+pub fn all_instructions(memory: &[u8]) -> String {
     let mut disassembly = String::from("bits 16\n");
-    super::decode::all_instructions_into(&mut memory, &mut disassembly);
+    super::decode::all_instructions_into(memory, &mut disassembly);
+    disassembly
+}
+
+/// Like [`all_instructions`], written in the syntax, case and radix of `style`.
+pub fn all_instructions_with(mut memory: &[u8], style: &Style) -> String {
+    let mut disassembly = format!("{}\n", style.header());
+    while !memory.is_empty() {
+        let (_, instruction) = single_instruction(&mut memory);
+        writeln!(disassembly, "{}", style.instruction(&instruction))
+            .expect("can write into the disassembly string");
+    }
     disassembly
 }
 
@@ -19,147 +34,467 @@ pub fn all_instructions_into(mut memory: &[u8], disassembly: &mut String) {
     }
 }
 
+/// Decodes the instruction at the start of `memory` and advances past it, returning how many bytes
+/// it took up. An opcode whose operands would run past the end of `memory` is reported as
+/// [`Instruction::Unrecognized`] and only the opcode byte itself is consumed.
 pub fn single_instruction(memory: &mut &[u8]) -> (usize, Instruction) {
     let mem_left_prior = memory.len();
-    let byte = advance(memory);
+    let start = *memory;
+    let instruction = decode(memory).unwrap_or_else(|| {
+        *memory = start.get(1..).unwrap_or_default();
+        Instruction::Unrecognized(start.first().copied().unwrap_or_default())
+    });
+    let mem_left_after = memory.len();
+    let offset = mem_left_prior - mem_left_after;
+
+    (offset, instruction)
+}
+
+/// Decodes the instruction following a prefix. Another prefix is never valid there, and refusing
+/// it keeps runs of prefix bytes from recursing once per byte.
+fn decode_prefixed(memory: &mut &[u8]) -> Option<Instruction> {
+    match memory.first()? {
+        0x26 | 0x2E | 0x36 | 0x3E | 0xF2 | 0xF3 => None,
+        _ => decode(memory),
+    }
+}
+
+fn decode(memory: &mut &[u8]) -> Option<Instruction> {
+    let byte = advance(memory)?;
 
     let instruction = match byte {
         // ARITHMETIC Reg/memory wyth register to either
-        0x00..=0x03 | 0x28..=0x2B | 0x38..=0x3B => {
-            let word_flag = (byte & 0b01) > 0;
+        0x00..=0x3F if byte & 0b100 == 0 => {
+            let width = Width::from_bit((byte & 0b01) > 0);
             let dest_flag = (byte & 0b10) > 0;
-            let op = (byte >> 3) & 0b111;
-            let (target, source) = target_source(word_flag, dest_flag, memory);
+            let op = ArithOp::from_octal((byte >> 3) & 0b111).unwrap();
+            let (target, source) = target_source(width, dest_flag, memory)?;
             Instruction::Arithmetic { op, target, source }
         }
-        // ADD Immediate to accumulator
-        0x04..=0x05 | 0x2C..=0x2D | 0x3C..=0x3D => {
-            let word_mode = (byte & 0x01) > 0;
-            let immediate = advance_by(memory, 1 + word_mode as usize) as u16;
-            let op = (byte >> 3) & 0b111;
-            let register = if word_mode {
-                Place::Word(RegisterWord::AX)
-            } else {
-                Place::Byte(RegisterByte::AL)
+        // ARITHMETIC Immediate to accumulator
+        0x00..=0x3F if byte & 0b110 == 0b100 => {
+            let width = Width::from_bit((byte & 0x01) > 0);
+            let immediate = signed_immediate(memory, width)?;
+            let op = ArithOp::from_octal((byte >> 3) & 0b111).unwrap();
+            let target = match width {
+                Width::Word => Place::Word(RegisterWord::AX),
+                Width::Byte => Place::Byte(RegisterByte::AL),
             };
             Instruction::ArithmeticImmediate {
                 op,
-                target: register,
+                width,
+                target,
                 immediate,
             }
         }
+        // PUSH/POP Segment register
+        0x06 | 0x0E | 0x16 | 0x1E => Instruction::Push {
+            target: segment_register(byte >> 3)?,
+        },
+        0x07 | 0x17 | 0x1F => Instruction::Pop {
+            target: segment_register(byte >> 3)?,
+        },
+        // Segment override prefix, applying to the memory operand of the following instruction
+        0x26 | 0x2E | 0x36 | 0x3E => {
+            let segment = SegmentRegister::from_octal((byte >> 3) & 0b11);
+            let mut instruction = decode_prefixed(memory)?;
+            let address = instruction.memory_operand_mut()?;
+            if address.segment.is_some() {
+                return None;
+            }
+            address.segment = segment;
+            instruction
+        }
+        // INC/DEC Register
+        0x40..=0x4F => Instruction::Unary {
+            op: if byte & 0b1000 == 0 {
+                UnaryOp::Inc
+            } else {
+                UnaryOp::Dec
+            },
+            width: Width::Word,
+            target: Place::register(Width::Word, byte & MASK_REG),
+        },
+        // PUSH/POP Register
+        0x50..=0x57 => Instruction::Push {
+            target: Place::register(Width::Word, byte & MASK_REG),
+        },
+        0x58..=0x5F => Instruction::Pop {
+            target: Place::register(Width::Word, byte & MASK_REG),
+        },
         0x70..=0x7F => {
-            let offset = advance(memory) as i8;
+            let offset = advance(memory)? as i8;
             Instruction::Jump {
-                marker: byte,
+                condition: JumpCondition::from_opcode(byte),
                 offset,
             }
         }
         // LOOPS
         0xE0..=0xE3 => {
-            let offset = advance(memory) as i8;
+            let offset = advance(memory)? as i8;
             Instruction::Loop {
-                marker: byte,
+                kind: LoopKind::from_opcode(byte),
                 offset,
             }
         }
         // ARITHMETIC Immediate to register/memory
         0x80..=0x83 => {
-            let word_mode = (byte & 0b01) > 0;
-
-            // TODO (matyas): It's called sign extension because it's used when working with a 16 bit register
-            // but there is only an 8 bit immediate that is negative, and the sign bit needs to move
-            // from being the topmost bit of the 8 bits to being the topmost bit of the 16 bits.
+            let width = Width::from_bit((byte & 0b01) > 0);
+            // With the sign extension bit set, a word operation only carries an 8 bit immediate
+            // whose sign bit has to be moved up to the top of the full 16 bits.
             let sign_extension = (byte & 0b10) > 0;
 
-            let (mode, op, r_m) = mod_reg_rm(memory);
+            let (mode, op, r_m) = mod_reg_rm(memory)?;
             let mode = Mode::from_u8_discriminant(mode).unwrap();
-            let target = Place::resolve_rm(r_m, mode, word_mode, memory);
+            let target = Place::resolve_rm(r_m, mode, width, memory)?;
 
-            let immediate =
-                advance_by(memory, 1 + ((word_mode && !sign_extension) as usize)) as u16;
+            let immediate_width = if sign_extension { Width::Byte } else { width };
+            let immediate = signed_immediate(memory, immediate_width)?;
 
-            Instruction::ArithmeticImmediateToMemory {
-                word_mode,
-                op,
+            Instruction::ArithmeticImmediate {
+                op: ArithOp::from_octal(op).unwrap(),
+                width,
                 target,
                 immediate,
             }
         }
+        // TEST Register/memory and register
+        0x84 | 0x85 => {
+            let width = Width::from_bit((byte & 0b01) > 0);
+            let (target, source) = target_source(width, false, memory)?;
+            Instruction::Test { target, source }
+        }
+        // XCHG Register/memory with register
+        0x86 | 0x87 => {
+            let width = Width::from_bit((byte & 0b01) > 0);
+            let (target, source) = target_source(width, true, memory)?;
+            Instruction::Xchg { target, source }
+        }
+        // XCHG Register with accumulator
+        0x90..=0x97 => Instruction::Xchg {
+            target: Place::Word(RegisterWord::AX),
+            source: Place::register(Width::Word, byte & MASK_REG),
+        },
+        // LEA, LDS, LES Load address or pointer into register
+        0x8D | 0xC4 | 0xC5 => {
+            let op = match byte {
+                0x8D => LoadOp::Lea,
+                0xC4 => LoadOp::Les,
+                _ => LoadOp::Lds,
+            };
+            let (target, source) = target_source(Width::Word, true, memory)?;
+            if !matches!(source, Place::Adress(_)) {
+                return None;
+            }
+            Instruction::Load { op, target, source }
+        }
+        // POP Register/memory
+        0x8F => {
+            let (mode, reg, r_m) = mod_reg_rm(memory)?;
+            if reg != 0 {
+                return None;
+            }
+            let mode = Mode::from_u8_discriminant(mode).unwrap();
+            Instruction::Pop {
+                target: Place::resolve_rm(r_m, mode, Width::Word, memory)?,
+            }
+        }
+        // CALL Direct intersegment
+        0x9A => Instruction::Call(far_pointer(memory)?),
+        // TEST Immediate and accumulator
+        0xA8 | 0xA9 => {
+            let width = Width::from_bit((byte & 0b01) > 0);
+            let immediate = signed_immediate(memory, width)?;
+            Instruction::TestImmediate {
+                width,
+                target: Place::register(width, 0),
+                immediate,
+            }
+        }
+        // TEST Immediate, NOT, NEG, MUL, IMUL, DIV, IDIV Register/memory
+        0xF6 | 0xF7 => {
+            let width = Width::from_bit((byte & 0b01) > 0);
+            let (mode, op, r_m) = mod_reg_rm(memory)?;
+            let mode = Mode::from_u8_discriminant(mode).unwrap();
+            let target = Place::resolve_rm(r_m, mode, width, memory)?;
+            match op {
+                0b000 | 0b001 => {
+                    let immediate = signed_immediate(memory, width)?;
+                    Instruction::TestImmediate {
+                        width,
+                        target,
+                        immediate,
+                    }
+                }
+                _ => {
+                    const OPS: [UnaryOp; 6] = [
+                        UnaryOp::Not,
+                        UnaryOp::Neg,
+                        UnaryOp::Mul,
+                        UnaryOp::Imul,
+                        UnaryOp::Div,
+                        UnaryOp::Idiv,
+                    ];
+                    Instruction::Unary {
+                        op: OPS[op as usize - 2],
+                        width,
+                        target,
+                    }
+                }
+            }
+        }
+        // INC, DEC Register/memory
+        0xFE => {
+            let (mode, op, r_m) = mod_reg_rm(memory)?;
+            let mode = Mode::from_u8_discriminant(mode).unwrap();
+            let target = Place::resolve_rm(r_m, mode, Width::Byte, memory)?;
+            let op = match op {
+                0b000 => UnaryOp::Inc,
+                0b001 => UnaryOp::Dec,
+                _ => return None,
+            };
+            Instruction::Unary {
+                op,
+                width: Width::Byte,
+                target,
+            }
+        }
+        // INC, DEC, CALL, JMP, PUSH Register/memory
+        0xFF => {
+            let (mode, op, r_m) = mod_reg_rm(memory)?;
+            let mode = Mode::from_u8_discriminant(mode).unwrap();
+            let target = Place::resolve_rm(r_m, mode, Width::Word, memory)?;
+            let is_memory = matches!(target, Place::Adress(_));
+            match op {
+                0b000 | 0b001 => Instruction::Unary {
+                    op: if op == 0 { UnaryOp::Inc } else { UnaryOp::Dec },
+                    width: Width::Word,
+                    target,
+                },
+                0b010 => Instruction::Call(Branch::Indirect(target)),
+                0b011 if is_memory => Instruction::Call(Branch::FarIndirect(target)),
+                0b100 => Instruction::Jmp(Branch::Indirect(target)),
+                0b101 if is_memory => Instruction::Jmp(Branch::FarIndirect(target)),
+                0b110 => Instruction::Push { target },
+                _ => return None,
+            }
+        }
+        // SHIFT/ROTATE Register/memory by 1 or by CL
+        0xD0..=0xD3 => {
+            let width = Width::from_bit((byte & 0b01) > 0);
+            let by_cl = (byte & 0b10) > 0;
+            let (mode, op, r_m) = mod_reg_rm(memory)?;
+            let mode = Mode::from_u8_discriminant(mode).unwrap();
+            let target = Place::resolve_rm(r_m, mode, width, memory)?;
+            Instruction::Shift {
+                op: ShiftOp::from_octal(op).unwrap(),
+                width,
+                target,
+                by_cl,
+            }
+        }
+        // IN/OUT Fixed or variable port
+        0xE4..=0xE7 | 0xEC..=0xEF => {
+            let width = Width::from_bit((byte & 0b01) > 0);
+            let port = if byte & 0b1000 > 0 {
+                Port::Dx
+            } else {
+                Port::Immediate(advance(memory)?)
+            };
+            if byte & 0b10 > 0 {
+                Instruction::Out { width, port }
+            } else {
+                Instruction::In { width, port }
+            }
+        }
         // MOV Register/memory to/from register
-        0x88..=0x8C => {
-            let word_mode = (byte & 0b01) > 0;
+        0x88..=0x8B => {
+            let width = Width::from_bit((byte & 0b01) > 0);
             let dest_mode = (byte & 0b10) > 0;
 
-            let (target, source) = target_source(word_mode, dest_mode, memory);
+            let (target, source) = target_source(width, dest_mode, memory)?;
             Instruction::Mov { target, source }
         }
+        // MOV Segment register to/from register/memory
+        0x8C | 0x8E => {
+            let (mode, reg, r_m) = mod_reg_rm(memory)?;
+            let mode = Mode::from_u8_discriminant(mode).unwrap();
+            let segment = segment_register(reg)?;
+            let r_m = Place::resolve_rm(r_m, mode, Width::Word, memory)?;
+            if byte & 0b10 > 0 {
+                Instruction::Mov {
+                    target: segment,
+                    source: r_m,
+                }
+            } else {
+                Instruction::Mov {
+                    target: r_m,
+                    source: segment,
+                }
+            }
+        }
+        // MOV Memory to/from accumulator
+        0xA0..=0xA3 => {
+            let width = Width::from_bit((byte & 0b01) > 0);
+            let accumulator = Place::register(width, 0);
+            let address = Place::direct(memory)?;
+            if byte & 0b10 > 0 {
+                Instruction::Mov {
+                    target: address,
+                    source: accumulator,
+                }
+            } else {
+                Instruction::Mov {
+                    target: accumulator,
+                    source: address,
+                }
+            }
+        }
+        // String manipulation, optionally repeated
+        0xA4..=0xA7 | 0xAA..=0xAF => Instruction::StringOperation {
+            op: StringOp::from_opcode(byte)?,
+            width: Width::from_bit((byte & 0b01) > 0),
+            repeat: None,
+        },
+        0xF2 | 0xF3 => {
+            let Instruction::StringOperation {
+                op,
+                width,
+                repeat: None,
+            } = decode_prefixed(memory)?
+            else {
+                return None;
+            };
+            let repeat = if byte & 0b01 > 0 {
+                Repeat::Rep
+            } else {
+                Repeat::Repne
+            };
+            Instruction::StringOperation {
+                op,
+                width,
+                repeat: Some(repeat),
+            }
+        }
         // MOV Immediate
         0xB0..=0xBF => {
-            let word_mode = (byte & 0b1000) > 0;
+            let width = Width::from_bit((byte & 0b1000) > 0);
             let reg = byte & MASK_REG;
 
-            let target = Place::register(word_mode, reg);
-            let immediate = advance_by(memory, 1 + word_mode as usize) as u16;
-            Instruction::MovImmediate { target, immediate }
+            let target = Place::register(width, reg);
+            let immediate = signed_immediate(memory, width)?;
+            Instruction::MovImmediate {
+                width,
+                target,
+                immediate,
+            }
         }
         0xC6 | 0xC7 => {
-            let word_mode = (byte & 0b01) > 0;
-            let (mode, _, r_m) = mod_reg_rm(memory);
+            let width = Width::from_bit((byte & 0b01) > 0);
+            let (mode, _, r_m) = mod_reg_rm(memory)?;
             let mode = Mode::from_u8_discriminant(mode).unwrap();
-            let target = Place::resolve_rm(r_m, mode, word_mode, memory);
-            let immediate = advance_by(memory, 1 + word_mode as usize) as u16;
-            Instruction::MovImmediateToMemory {
-                word_mode,
+            let target = Place::resolve_rm(r_m, mode, width, memory)?;
+            let immediate = signed_immediate(memory, width)?;
+            Instruction::MovImmediate {
+                width,
                 target,
                 immediate,
             }
         }
-        _ => Instruction::Unrecognized(byte),
+        // RET Within segment or intersegment, optionally adding to SP
+        0xC2 | 0xC3 | 0xCA | 0xCB => {
+            let pop = if byte & 0b01 == 0 {
+                Some(advance_by(memory, 2)? as u16)
+            } else {
+                None
+            };
+            Instruction::Return {
+                far: byte & 0b1000 > 0,
+                pop,
+            }
+        }
+        // INT Type specified
+        0xCD => Instruction::Interrupt(advance(memory)?),
+        // AAM, AAD, whose second byte is always 0x0A
+        0xD4 | 0xD5 => {
+            if advance(memory)? != 0x0A {
+                return None;
+            }
+            Instruction::Implied(if byte == 0xD4 {
+                ImpliedOp::Aam
+            } else {
+                ImpliedOp::Aad
+            })
+        }
+        // CALL, JMP Direct within segment
+        0xE8 => Instruction::Call(Branch::Near(advance_by(memory, 2)? as u16 as i16)),
+        0xE9 => Instruction::Jmp(Branch::Near(advance_by(memory, 2)? as u16 as i16)),
+        // JMP Direct intersegment
+        0xEA => Instruction::Jmp(far_pointer(memory)?),
+        // JMP Direct within segment-short
+        0xEB => Instruction::Jmp(Branch::Short(advance(memory)? as i8)),
+        _ => Instruction::Implied(ImpliedOp::from_opcode(byte)?),
     };
-    let mem_left_after = memory.len();
-    let offset = mem_left_prior - mem_left_after;
-
-    (offset, instruction)
+    Some(instruction)
 }
 
-fn target_source(word_mode: bool, dest_mode: bool, memory: &mut &[u8]) -> (Place, Place) {
-    let (mode, reg, r_m) = mod_reg_rm(memory);
+fn target_source(width: Width, dest_mode: bool, memory: &mut &[u8]) -> Option<(Place, Place)> {
+    let (mode, reg, r_m) = mod_reg_rm(memory)?;
     let mode = Mode::from_u8_discriminant(mode).unwrap();
-    let reg = Place::register(word_mode, reg);
-    let r_m = Place::resolve_rm(r_m, mode, word_mode, memory);
+    let reg = Place::register(width, reg);
+    let r_m = Place::resolve_rm(r_m, mode, width, memory)?;
     if dest_mode {
-        (reg, r_m)
+        Some((reg, r_m))
     } else {
-        (r_m, reg)
+        Some((r_m, reg))
     }
 }
 
-fn mod_reg_rm(memory: &mut &[u8]) -> (u8, u8, u8) {
-    let mut byte = advance(memory);
+/// The segment register encoded in the low two bits of `octal`, or `None` if the third bit is set.
+fn segment_register(octal: u8) -> Option<Place> {
+    SegmentRegister::from_octal(octal & MASK_REG).map(Place::Segment)
+}
+
+/// Reads an intersegment address, offset first.
+fn far_pointer(memory: &mut &[u8]) -> Option<Branch> {
+    let offset = advance_by(memory, 2)? as u16;
+    let segment = advance_by(memory, 2)? as u16;
+    Some(Branch::Far { segment, offset })
+}
+
+/// Reads a word immediate, or a byte immediate sign extended to a word.
+fn signed_immediate(memory: &mut &[u8], width: Width) -> Option<i16> {
+    match width {
+        Width::Word => Some(advance_by(memory, 2)? as u16 as i16),
+        Width::Byte => Some(advance(memory)? as i8 as i16),
+    }
+}
+
+fn mod_reg_rm(memory: &mut &[u8]) -> Option<(u8, u8, u8)> {
+    let mut byte = advance(memory)?;
     let r_m = byte & MASK_REG;
     byte >>= 3;
     let reg = byte & MASK_REG;
     byte >>= 3;
-    (byte, reg, r_m)
+    Some((byte, reg, r_m))
 }
 
-fn advance(memory: &mut &[u8]) -> u8 {
-    if memory.is_empty() {
-        return 0;
-    }
-    let byte = memory[0];
-    *memory = &memory[1..];
-    byte
+fn advance(memory: &mut &[u8]) -> Option<u8> {
+    let (&byte, rest) = memory.split_first()?;
+    *memory = rest;
+    Some(byte)
 }
 
-pub fn advance_by(memory: &mut &[u8], width: usize) -> usize {
+/// Reads a little endian integer `width` bytes wide, or `None` if there are not enough bytes left.
+pub fn advance_by(memory: &mut &[u8], width: usize) -> Option<usize> {
     let displacement_bytes;
-    (displacement_bytes, *memory) = memory.split_at(width);
-    displacement_bytes
-        .iter()
-        .rev()
-        .fold(0usize, |acc, byte| 256 * acc + *byte as usize)
+    (displacement_bytes, *memory) = memory.split_at_checked(width)?;
+    Some(
+        displacement_bytes
+            .iter()
+            .rev()
+            .fold(0usize, |acc, byte| 256 * acc + *byte as usize),
+    )
 }
 
 const MASK_REG: u8 = 0b00000111;
@@ -0,0 +1,365 @@
+//! Enumeration of the machine encodings of an instruction.
+//!
+//! Many instructions can be encoded in more than one way: register to register operations can put
+//! either register into the `reg` field, AL and AX have shorter opcodes of their own, small word
+//! immediates fit into a sign extended byte and a displacement can be left out when it is zero or
+//! sign extended from a byte when it fits. [`encodings`] lists them all, each of which
+//! [`decode`](crate::decode) turns back into the very same instruction.
+
+use crate::{
+    AddressBase, Branch, EffectiveAdress, ImpliedOp, Instruction, LoadOp, Mode, Place, Port,
+    Repeat, SegmentRegister, UnaryOp, Width,
+};
+
+/// What sets an encoding apart from the others of the same instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Form {
+    /// The general register/memory form, or the only one there is.
+    General,
+    /// The register/memory form with the direction bit set, so the `reg` field is the target.
+    Direction,
+    /// A shorter opcode dedicated to AL or AX.
+    Accumulator,
+    /// A single byte opcode with the register in its low three bits.
+    ShortRegister,
+    /// A byte immediate sign extended to the word the instruction works with.
+    SignExtended,
+    /// An undocumented opcode or `reg` field the 8086 treats like the documented one.
+    Alias,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Encoding {
+    pub form: Form,
+    pub bytes: Vec<u8>,
+}
+
+/// Every way of encoding `instruction`, starting with the general form. A memory operand makes
+/// the forms repeat for every width its displacement can be encoded in, the narrowest first.
+/// Instructions the decoder never produces, like a `test` with a memory source, have none.
+pub fn encodings(instruction: &Instruction) -> Vec<Encoding> {
+    let mut encodings = Encodings(Vec::new());
+    let mut variant = *instruction;
+    match variant.memory_operand_mut().copied() {
+        Some(address) => {
+            for mode in displacement_modes(&address) {
+                let operand = variant
+                    .memory_operand_mut()
+                    .expect("the variant has the operands of the instruction");
+                *operand = EffectiveAdress { mode, ..address };
+                encodings.add_all(&variant);
+            }
+        }
+        None => encodings.add_all(instruction),
+    }
+
+    // the segment override goes in front of every form alike
+    let mut instruction = *instruction;
+    let segment = instruction
+        .memory_operand_mut()
+        .and_then(|address| address.segment);
+    if let Some(segment) = segment {
+        let prefix = 0x26 | (segment as u8) << 3;
+        for encoding in &mut encodings.0 {
+            encoding.bytes.insert(0, prefix);
+        }
+    }
+    encodings.0
+}
+
+struct Encodings(Vec<Encoding>);
+
+impl Encodings {
+    fn push(&mut self, form: Form, parts: &[&[u8]]) {
+        self.0.push(Encoding {
+            form,
+            bytes: parts.concat(),
+        });
+    }
+
+    fn add_all(&mut self, instruction: &Instruction) {
+        use Form::*;
+        let w = (instruction.width() == Width::Word) as u8;
+        match *instruction {
+            Instruction::Arithmetic { op, target, source } => {
+                self.reg_rm((op as u8) << 3 | w, &target, &source)
+            }
+            Instruction::ArithmeticImmediate {
+                op,
+                width,
+                target,
+                immediate,
+            } => {
+                let op = op as u8;
+                let imm = immediate_bytes(immediate, width);
+                let modrm = mod_reg_rm(op, &target);
+                self.push(General, &[&[0x80 | w], &modrm, &imm]);
+                match width {
+                    Width::Byte => self.push(Alias, &[&[0x82], &modrm, &imm]),
+                    Width::Word if i8::try_from(immediate).is_ok() => {
+                        self.push(SignExtended, &[&[0x83], &modrm, &[immediate as u8]])
+                    }
+                    Width::Word => (),
+                }
+                if is_accumulator(&target) {
+                    self.push(Accumulator, &[&[op << 3 | 0b100 | w], &imm]);
+                }
+            }
+            Instruction::Jump { condition, offset } => {
+                self.push(General, &[&[0x70 | condition as u8, offset as u8]])
+            }
+            Instruction::Loop { kind, offset } => {
+                self.push(General, &[&[0xE0 | kind as u8, offset as u8]])
+            }
+            Instruction::Shift {
+                op, target, by_cl, ..
+            } => {
+                let opcode = 0xD0 | (by_cl as u8) << 1 | w;
+                self.push(General, &[&[opcode], &mod_reg_rm(op as u8, &target)])
+            }
+            Instruction::Test { target, source } => {
+                if let Some(reg) = register(&source) {
+                    self.push(General, &[&[0x84 | w], &mod_reg_rm(reg, &target)]);
+                }
+            }
+            Instruction::TestImmediate {
+                width,
+                target,
+                immediate,
+            } => {
+                let imm = immediate_bytes(immediate, width);
+                self.push(General, &[&[0xF6 | w], &mod_reg_rm(0, &target), &imm]);
+                self.push(Alias, &[&[0xF6 | w], &mod_reg_rm(1, &target), &imm]);
+                if is_accumulator(&target) {
+                    self.push(Accumulator, &[&[0xA8 | w], &imm]);
+                }
+            }
+            Instruction::Unary { op, target, .. } => {
+                let (opcode, reg) = match op {
+                    UnaryOp::Inc | UnaryOp::Dec => (0xFE | w, (op == UnaryOp::Dec) as u8),
+                    UnaryOp::Not => (0xF6 | w, 2),
+                    UnaryOp::Neg => (0xF6 | w, 3),
+                    UnaryOp::Mul => (0xF6 | w, 4),
+                    UnaryOp::Imul => (0xF6 | w, 5),
+                    UnaryOp::Div => (0xF6 | w, 6),
+                    UnaryOp::Idiv => (0xF6 | w, 7),
+                };
+                self.push(General, &[&[opcode], &mod_reg_rm(reg, &target)]);
+                if let (0xFF, Some(register)) = (opcode, register(&target)) {
+                    self.push(ShortRegister, &[&[0x40 | reg << 3 | register]]);
+                }
+            }
+            Instruction::Push { target } => match target {
+                Place::Segment(segment) => self.push(General, &[&[0x06 | (segment as u8) << 3]]),
+                _ => {
+                    self.push(General, &[&[0xFF], &mod_reg_rm(6, &target)]);
+                    if let Some(register) = register(&target) {
+                        self.push(ShortRegister, &[&[0x50 | register]]);
+                    }
+                }
+            },
+            Instruction::Pop { target } => match target {
+                // the opcode `pop CS` would have is the two byte escape on later processors
+                Place::Segment(SegmentRegister::CS) => (),
+                Place::Segment(segment) => self.push(General, &[&[0x07 | (segment as u8) << 3]]),
+                _ => {
+                    self.push(General, &[&[0x8F], &mod_reg_rm(0, &target)]);
+                    if let Some(register) = register(&target) {
+                        self.push(ShortRegister, &[&[0x58 | register]]);
+                    }
+                }
+            },
+            Instruction::Xchg { target, source } => {
+                if let Some(reg) = register(&target) {
+                    self.push(General, &[&[0x86 | w], &mod_reg_rm(reg, &source)]);
+                }
+                if let (Place::Word(_), Some(0), Some(register)) =
+                    (target, register(&target), register(&source))
+                {
+                    self.push(ShortRegister, &[&[0x90 | register]]);
+                }
+            }
+            Instruction::Load { op, target, source } => {
+                if let (Some(reg), Place::Adress(_)) = (register(&target), source) {
+                    let opcode = match op {
+                        LoadOp::Lea => 0x8D,
+                        LoadOp::Les => 0xC4,
+                        LoadOp::Lds => 0xC5,
+                    };
+                    self.push(General, &[&[opcode], &mod_reg_rm(reg, &source)]);
+                }
+            }
+            Instruction::StringOperation { op, repeat, .. } => {
+                let opcode = 0xA0 | (op as u8) << 1 | w;
+                match repeat {
+                    None => self.push(General, &[&[opcode]]),
+                    Some(Repeat::Rep) => self.push(General, &[&[0xF3, opcode]]),
+                    Some(Repeat::Repne) => self.push(General, &[&[0xF2, opcode]]),
+                }
+            }
+            Instruction::Call(branch) => match branch {
+                Branch::Near(offset) => self.push(General, &[&[0xE8], &offset.to_le_bytes()]),
+                Branch::Indirect(target) => self.push(General, &[&[0xFF], &mod_reg_rm(2, &target)]),
+                Branch::FarIndirect(target) => {
+                    self.push(General, &[&[0xFF], &mod_reg_rm(3, &target)])
+                }
+                Branch::Far { segment, offset } => self.push(
+                    General,
+                    &[&[0x9A], &offset.to_le_bytes(), &segment.to_le_bytes()],
+                ),
+                // there's no short call
+                Branch::Short(_) => (),
+            },
+            Instruction::Jmp(branch) => match branch {
+                Branch::Short(offset) => self.push(General, &[&[0xEB, offset as u8]]),
+                Branch::Near(offset) => self.push(General, &[&[0xE9], &offset.to_le_bytes()]),
+                Branch::Indirect(target) => self.push(General, &[&[0xFF], &mod_reg_rm(4, &target)]),
+                Branch::FarIndirect(target) => {
+                    self.push(General, &[&[0xFF], &mod_reg_rm(5, &target)])
+                }
+                Branch::Far { segment, offset } => self.push(
+                    General,
+                    &[&[0xEA], &offset.to_le_bytes(), &segment.to_le_bytes()],
+                ),
+            },
+            Instruction::Return { far, pop } => {
+                let opcode = 0xC2 | (far as u8) << 3;
+                match pop {
+                    Some(pop) => self.push(General, &[&[opcode], &pop.to_le_bytes()]),
+                    None => self.push(General, &[&[opcode | 1]]),
+                }
+            }
+            Instruction::Interrupt(vector) => self.push(General, &[&[0xCD, vector]]),
+            Instruction::Implied(ImpliedOp::Aam) => self.push(General, &[&[0xD4, 0x0A]]),
+            Instruction::Implied(ImpliedOp::Aad) => self.push(General, &[&[0xD5, 0x0A]]),
+            Instruction::Implied(op) => {
+                let opcode =
+                    (0..=u8::MAX).find(|&opcode| ImpliedOp::from_opcode(opcode) == Some(op));
+                if let Some(opcode) = opcode {
+                    self.push(General, &[&[opcode]]);
+                }
+            }
+            Instruction::In { port, .. } => match port {
+                Port::Immediate(port) => self.push(General, &[&[0xE4 | w, port]]),
+                Port::Dx => self.push(General, &[&[0xEC | w]]),
+            },
+            Instruction::Out { port, .. } => match port {
+                Port::Immediate(port) => self.push(General, &[&[0xE6 | w, port]]),
+                Port::Dx => self.push(General, &[&[0xEE | w]]),
+            },
+            Instruction::Mov {
+                target: Place::Segment(segment),
+                source,
+            } => self.push(General, &[&[0x8E], &mod_reg_rm(segment as u8, &source)]),
+            Instruction::Mov {
+                target,
+                source: Place::Segment(segment),
+            } => self.push(General, &[&[0x8C], &mod_reg_rm(segment as u8, &target)]),
+            Instruction::Mov { target, source } => {
+                self.reg_rm(0x88 | w, &target, &source);
+                match (target, source) {
+                    (Place::Adress(address), _)
+                        if address.is_direct() && is_accumulator(&source) =>
+                    {
+                        self.push(
+                            Accumulator,
+                            &[&[0xA2 | w], &address.displacement.to_le_bytes()],
+                        )
+                    }
+                    (_, Place::Adress(address))
+                        if address.is_direct() && is_accumulator(&target) =>
+                    {
+                        self.push(
+                            Accumulator,
+                            &[&[0xA0 | w], &address.displacement.to_le_bytes()],
+                        )
+                    }
+                    _ => (),
+                }
+            }
+            Instruction::MovImmediate {
+                width,
+                target,
+                immediate,
+            } => {
+                let imm = immediate_bytes(immediate, width);
+                self.push(General, &[&[0xC6 | w], &mod_reg_rm(0, &target), &imm]);
+                // the 8086 ignores the reg field of this one
+                for reg in 1..8 {
+                    self.push(Alias, &[&[0xC6 | w], &mod_reg_rm(reg, &target), &imm]);
+                }
+                if let Some(register) = register(&target) {
+                    self.push(ShortRegister, &[&[0xB0 | w << 3 | register], &imm]);
+                }
+            }
+            Instruction::Unrecognized(_) => (),
+        }
+    }
+
+    /// Both directions of a register/memory with register operation, as far as the operands
+    /// allow.
+    fn reg_rm(&mut self, opcode: u8, target: &Place, source: &Place) {
+        if let Some(reg) = register(source) {
+            self.push(Form::General, &[&[opcode], &mod_reg_rm(reg, target)]);
+        }
+        if let Some(reg) = register(target) {
+            self.push(
+                Form::Direction,
+                &[&[opcode | 0b10], &mod_reg_rm(reg, source)],
+            );
+        }
+    }
+}
+
+/// Number of a general purpose register in the `reg` and `r/m` fields.
+fn register(place: &Place) -> Option<u8> {
+    match place {
+        Place::Byte(register) => Some(*register as u8),
+        Place::Word(register) => Some(*register as u8),
+        Place::Segment(_) | Place::Adress(_) => None,
+    }
+}
+
+fn is_accumulator(place: &Place) -> bool {
+    register(place) == Some(0)
+}
+
+/// The mod r/m byte with `reg` in its middle field, followed by the displacement `place` needs.
+fn mod_reg_rm(reg: u8, place: &Place) -> Vec<u8> {
+    let Place::Adress(address) = place else {
+        return vec![0b11 << 6 | reg << 3 | register(place).unwrap_or_default()];
+    };
+    let mut bytes = vec![(address.mode as u8) << 6 | reg << 3 | address.index];
+    let displacement = address.displacement.to_le_bytes();
+    match address.mode {
+        Mode::EffectiveAdressByte => bytes.push(displacement[0]),
+        Mode::EffectiveAdressWord => bytes.extend(displacement),
+        _ if address.is_direct() => bytes.extend(displacement),
+        _ => (),
+    }
+    bytes
+}
+
+/// The modes able to encode the displacement of `address`, the narrowest first.
+fn displacement_modes(address: &EffectiveAdress) -> Vec<Mode> {
+    if address.is_direct() {
+        return vec![Mode::EffectiveAdress];
+    }
+    let mut modes = Vec::new();
+    // BP on its own without a displacement is how direct addresses are encoded
+    if address.displacement == 0 && address.base() != Some(AddressBase::Bp) {
+        modes.push(Mode::EffectiveAdress);
+    }
+    if i8::try_from(address.displacement).is_ok() {
+        modes.push(Mode::EffectiveAdressByte);
+    }
+    modes.push(Mode::EffectiveAdressWord);
+    modes
+}
+
+fn immediate_bytes(immediate: i16, width: Width) -> Vec<u8> {
+    match width {
+        Width::Byte => vec![immediate as u8],
+        Width::Word => immediate.to_le_bytes().to_vec(),
+    }
+}
@@ -1,165 +1,699 @@
-use std::{
-    io::Read,
-    ops::{Index, IndexMut},
+use std::ops::{Index, IndexMut, Range, RangeInclusive};
+
+use crate::{
+    cycles, decode,
+    listing::SourceMap,
+    ports::{PortBus, PortDevice},
+    prefetch::{BusUnit, Cpu},
+    stats::Statistics,
+    watch::{AccessKind, CodeKind, CodeWrite, MemoryAccess, Watchpoint, Watchpoints},
+    AddressBase, ArithOp, EffectiveAdress, Instruction, Place, RegisterByte, RegisterWord,
+    SegmentRegister, ShiftOp, Width,
 };
 
-use crate::{decode, Place, RegisterByte, RegisterWord};
-
-pub fn all_instructions_and_print(state: &mut State) {
-    while state.instruction_pointer < state.program_end {
-        let (offset, instruction) =
-            decode::single_instruction(&mut &state.memory[state.instruction_pointer..]);
-        print!("; IP: {}\n{instruction}", state.instruction_pointer);
-        state.instruction_pointer += offset;
-
-        let flags_prior = state.registers.flags_string();
-        if let Some(target) = instruction.target() {
-            // TODO: generalize this for all cases. All that is needed is a function for getting
-            // the value from state indexed by Place
-            match target {
-                Place::Byte(reg) => {
-                    print!("; {target}: {:x} -> ", state.registers[reg]);
-                    instruction.run(state);
-                    print!("{:x}", state.registers[reg]);
-                }
-                Place::Word(reg) => {
-                    print!("; {target}: {:x} -> ", state.registers[reg]);
-                    instruction.run(state);
-                    print!("{:x}", state.registers[reg]);
-                }
-                Place::Adress(address) => match address.mode {
-                    crate::Mode::EffectiveAdress => match address.index {
-                        0b110 => {
-                            let hi = state.memory[address.displacement as usize];
-                            let lo = state.memory[(address.displacement + 1) as usize];
-                            let value = u16::from_be_bytes([hi, lo]);
-                            print!("; {target}: {value:x} -> ");
-                            instruction.run(state);
-                            let hi = state.memory[address.displacement as usize];
-                            let lo = state.memory[(address.displacement + 1) as usize];
-                            let value = u16::from_be_bytes([hi, lo]);
-                            print!("{value:x}");
-                        }
-                        0b010 => {
-                            let i = (state.registers[RegisterWord::BP]
-                                + state.registers[RegisterWord::SI]
-                                + address.displacement)
-                                as usize;
-                            let hi = state.memory[i];
-                            let lo = state.memory[i + 1];
-                            let value = u16::from_be_bytes([hi, lo]);
-                            print!("; {target}: {value:x} -> ");
-                            instruction.run(state);
-                            let hi = state.memory[i];
-                            let lo = state.memory[i + 1];
-                            let value = u16::from_be_bytes([hi, lo]);
-                            print!("{value:x}");
-                        }
-                        _ => todo!(),
-                    },
-                    crate::Mode::EffectiveAdressByte => {
-                        let i: usize = (match address.index {
-                            0b111 => state.registers[RegisterWord::BX],
-                            0b110 => state.registers[RegisterWord::BP],
-                            _ => todo!(),
-                        } + address.displacement)
-                            .into();
-                        let hi = state.memory[i];
-                        let lo = state.memory[i + 1];
-                        let value = u16::from_be_bytes([hi, lo]);
-                        print!("; {target}: {value:x} -> ");
-                        instruction.run(state);
-                        let hi = state.memory[i];
-                        let lo = state.memory[i + 1];
-                        let value = u16::from_be_bytes([hi, lo]);
-                        print!("{value:x}");
-                    }
-                    crate::Mode::EffectiveAdressWord => todo!(),
-                    crate::Mode::RegisterToRegister => todo!(),
-                },
+/// Why a simulation stopped before reaching the end of the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Unrecognized {
+        address: usize,
+        opcode: u8,
+    },
+    /// The instruction decodes fine, but the simulator can't execute it yet.
+    Unsupported {
+        address: usize,
+        instruction: Instruction,
+    },
+    /// A breaking watchpoint caught `access`, the instruction making it was fully executed.
+    Watchpoint(MemoryAccess),
+}
+
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::Unrecognized { address, opcode } => {
+                write!(
+                    f,
+                    "unrecognized opcode {opcode:#04x} at address {address:#06x}"
+                )
             }
-        } else {
-            instruction.run(state);
-        };
-        let flags_after = state.registers.flags_string();
-        if flags_after != flags_prior {
-            print!(" flags: {flags_prior} -> {flags_after}");
+            StopReason::Unsupported {
+                address,
+                instruction,
+            } => write!(
+                f,
+                "unsupported instruction `{instruction}` at address {address:#06x}"
+            ),
+            StopReason::Watchpoint(access) => write!(f, "watchpoint hit at {access}"),
         }
-        println!();
     }
 }
 
-union SplitRegister {
-    byte: SplitRegisterInner,
-    word: u16,
+/// The address `segment:offset` refers to. Addresses past the simulated memory wrap around to its
+/// start.
+pub fn physical_address(segment: u16, offset: u16) -> usize {
+    ((usize::from(segment) << 4) + usize::from(offset)) % MEMORY_SIZE
 }
-impl Default for SplitRegister {
-    fn default() -> Self {
-        Self { word: 0 }
+
+/// The physical addresses of the low and high byte of a word at `segment:offset`. A word at the
+/// very end of the segment wraps around to its start.
+fn byte_addresses(segment: u16, offset: u16) -> [usize; 2] {
+    [offset, offset.wrapping_add(1)].map(|offset| physical_address(segment, offset))
+}
+
+pub fn all_instructions(state: &mut State) -> Result<(), StopReason> {
+    while state.is_running() {
+        state.step();
+    }
+    state.stop.map_or(Ok(()), Err)
+}
+
+pub fn all_instructions_and_print(state: &mut State) -> Result<(), StopReason> {
+    while state.is_running() {
+        step_and_print(state);
+    }
+    state.stop.map_or(Ok(()), Err)
+}
+
+/// Executes a single instruction, printing it along with the changes it made. The instruction is
+/// printed as written in the [`State::source`] listing when it has a line for it.
+pub fn step_and_print(state: &mut State) {
+    let (_, instruction) = state.next_instruction();
+    let address = state.instruction_pointer;
+    let position = match state.registers[SegmentRegister::CS] {
+        0 => format!("IP: {}", state.ip()),
+        segment => format!("CS: {segment}, IP: {}", state.ip()),
+    };
+    match state
+        .source
+        .as_ref()
+        .and_then(|source| source.locate(address))
+    {
+        Some(location) => print!("; {position}, {location}\n{}", location.line.text),
+        None => print!("; {position}\n{instruction}"),
+    }
+    let code_writes_prior = state.code_writes.len();
+
+    let flags_prior = state.registers.flags_string();
+    if let Some(target) = instruction.target().filter(|_| instruction.is_simulated()) {
+        let width = instruction.width();
+        print!("; {target}: {:x} -> ", state.peek(target, width));
+        state.step();
+        print!("{:x}", state.peek(target, width));
+    } else {
+        state.step();
+    };
+    let flags_after = state.registers.flags_string();
+    if flags_after != flags_prior {
+        print!(" flags: {flags_prior} -> {flags_after}");
+    }
+    println!();
+    if state.warn_code_writes {
+        for write in &state.code_writes[code_writes_prior..] {
+            println!("; warning: {write}");
+        }
     }
 }
 
-#[derive(Clone, Copy, Default)]
-struct SplitRegisterInner {
-    h: u8,
-    l: u8,
+/// Running totals of the memory transfers made by executed instructions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryCounters {
+    pub reads: u64,
+    pub writes: u64,
+    /// Word transfers from or to an odd address, which the 8086 has to split in two.
+    pub unaligned_words: u64,
+    /// All word transfers, which the 8088 always splits in two.
+    pub words: u64,
+}
+
+impl std::ops::Sub for MemoryCounters {
+    type Output = Self;
+
+    fn sub(self, prior: Self) -> Self {
+        Self {
+            reads: self.reads - prior.reads,
+            writes: self.writes - prior.writes,
+            unaligned_words: self.unaligned_words - prior.unaligned_words,
+            words: self.words - prior.words,
+        }
+    }
 }
 
+/// Bytes of memory the 8086 can address, 1 MiB reached through 20 bit physical addresses.
+pub const MEMORY_SIZE: usize = 1 << 20;
+
 pub struct State {
     pub registers: Registers,
-    pub memory: [u8; u16::MAX as usize],
-    program_end: usize,
+    pub memory: Box<[u8]>,
+    pub(crate) program_end: usize,
+    /// Memory filled by [`State::load_at`], which may hold code reached by far jumps and calls.
+    loaded: Vec<Range<usize>>,
     pub instruction_pointer: usize,
+    pub memory_counters: MemoryCounters,
+    /// Collects execution statistics for every executed instruction when set.
+    pub statistics: Option<Statistics>,
+    /// Set once execution can't continue.
+    pub stop: Option<StopReason>,
+    /// Devices answering to `in` and `out`.
+    pub ports: PortBus,
+    /// Memory ranges whose accesses are logged or stop the simulation.
+    pub watchpoints: Watchpoints,
+    /// Address of the instruction being executed, for attributing memory accesses.
+    instruction_address: usize,
+    /// Models instruction fetch through the prefetch queue when set, for detailed timings.
+    pub bus: Option<BusUnit>,
+    /// Writes into code made by the program, see [`CodeKind`].
+    pub code_writes: Vec<CodeWrite>,
+    /// Print the writes into code as they happen in [`step_and_print`].
+    pub warn_code_writes: bool,
+    /// Listing the program was assembled from, for tracing the source instead of the disassembly.
+    pub source: Option<SourceMap>,
+    /// Which bytes of memory were part of an executed instruction.
+    executed: Box<[bool]>,
+    /// Bytes following the executing instruction that would already be in the prefetch queue.
+    pending: Range<usize>,
 }
 
 impl Default for State {
     fn default() -> Self {
         Self {
-            memory: [0; u16::MAX as usize],
+            memory: vec![0; MEMORY_SIZE].into_boxed_slice(),
             registers: Registers::default(),
             program_end: 0,
+            loaded: Vec::new(),
             instruction_pointer: 0,
+            memory_counters: MemoryCounters::default(),
+            statistics: None,
+            stop: None,
+            ports: PortBus::default(),
+            watchpoints: Watchpoints::default(),
+            instruction_address: 0,
+            bus: None,
+            code_writes: Vec::new(),
+            warn_code_writes: false,
+            source: None,
+            executed: vec![false; MEMORY_SIZE].into_boxed_slice(),
+            pending: 0..0,
         }
     }
 }
 impl State {
-    pub fn load_program(&mut self, path_bin: &str) {
-        let mut file = std::fs::File::open(path_bin).unwrap();
+    /// Loads `program` at the start of memory like [`State::load_bytes`], unless it doesn't fit.
+    pub fn load_program(&mut self, program: &[u8]) -> std::io::Result<()> {
+        if program.len() > self.memory.len() {
+            return Err(std::io::Error::other("program does not fit into memory"));
+        }
+        self.load_bytes(program);
+        Ok(())
+    }
+
+    pub fn load_bytes(&mut self, program: &[u8]) {
+        self.program_end = program.len();
+        self.memory[..program.len()].copy_from_slice(program);
+        self.executed.fill(false);
+    }
+
+    /// Copies `data` into memory at `address`, leaving the extent of the program alone.
+    pub fn load_at(&mut self, address: usize, data: &[u8]) -> std::io::Result<()> {
+        let end = address + data.len();
+        if end > self.memory.len() {
+            return Err(std::io::Error::other("data does not fit into memory"));
+        }
+        self.memory[address..end].copy_from_slice(data);
+        self.loaded.push(address..end);
+        Ok(())
+    }
+
+    /// Whether `address` lies within the program or data loaded next to it, the memory the
+    /// simulation runs code from.
+    pub fn is_code(&self, address: usize) -> bool {
+        address < self.program_end || self.loaded.iter().any(|range| range.contains(&address))
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.stop.is_none() && self.is_code(self.instruction_pointer)
+    }
+
+    /// Attaches `device` to the I/O `ports`, taking precedence over devices attached after it.
+    pub fn attach_device(&mut self, ports: RangeInclusive<u16>, device: impl PortDevice + 'static) {
+        self.ports.attach(ports, Box::new(device));
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.add(watchpoint);
+    }
+
+    /// Decodes the instruction at the instruction pointer without executing it.
+    pub fn next_instruction(&self) -> (usize, Instruction) {
+        decode::single_instruction(&mut &self.memory[self.instruction_pointer..])
+    }
+
+    /// Decodes and executes the instruction at the instruction pointer.
+    pub fn step(&mut self) -> Instruction {
+        let address = self.instruction_pointer;
+        let (offset, instruction) = self.next_instruction();
+        if let Instruction::Unrecognized(opcode) = instruction {
+            self.stop = Some(StopReason::Unrecognized { address, opcode });
+            return instruction;
+        }
+        if !instruction.is_simulated() {
+            self.stop = Some(StopReason::Unsupported {
+                address,
+                instruction,
+            });
+            return instruction;
+        }
+        let next = physical_address(
+            self.registers[SegmentRegister::CS],
+            self.ip().wrapping_add(offset as u16),
+        );
+        self.instruction_pointer = next;
+        self.instruction_address = address;
+        for i in address..address + offset {
+            self.executed[i % MEMORY_SIZE] = true;
+        }
+        let queue_size = self
+            .bus
+            .as_ref()
+            .map_or(Cpu::I8086, |bus| bus.cpu)
+            .queue_size();
+        self.pending = address + offset..address + offset + queue_size;
+
+        let counters_prior = self.memory_counters;
+        let shift_count = self.registers.byte(RegisterByte::CL);
+        instruction.run(self);
+        let jumped = self.instruction_pointer != next;
+
+        let count_cycles = self.statistics.as_ref().is_some_and(|s| s.count_cycles);
+        let mut clocks = if count_cycles || !self.ports.is_empty() || self.bus.is_some() {
+            let unaligned = self.memory_counters.unaligned_words - counters_prior.unaligned_words;
+            cycles::estimate(&instruction, jumped, shift_count).unwrap_or_default()
+                + unaligned as u32 * cycles::UNALIGNED_WORD_PENALTY
+        } else {
+            0
+        };
+        if let Some(bus) = &mut self.bus {
+            let transfers = self.memory_counters - counters_prior;
+            let jump_target = jumped.then_some(self.instruction_pointer);
+            clocks = bus.execute(offset, clocks, transfers, jump_target).clocks;
+        }
+        self.ports.tick(clocks);
+
+        if let Some(statistics) = &mut self.statistics {
+            statistics.record(
+                address,
+                &instruction,
+                jumped,
+                clocks,
+                (counters_prior, self.memory_counters),
+            );
+        }
+        instruction
+    }
+
+    /// The offset `address` refers to within its segment, as loaded by `lea`.
+    pub(crate) fn effective_offset(&self, address: EffectiveAdress) -> u16 {
+        let base = match address.base().map(AddressBase::registers) {
+            Some((base, Some(index))) => self.registers[base].wrapping_add(self.registers[index]),
+            Some((base, None)) => self.registers[base],
+            None => 0,
+        };
+        base.wrapping_add_signed(address.displacement())
+    }
+
+    /// The segment and offset `address` refers to. Addresses based on BP are in the stack segment,
+    /// all others in the data segment, unless a prefix overrides the segment.
+    fn effective_address(&self, address: EffectiveAdress) -> (u16, u16) {
+        use AddressBase::*;
+        let based_on_bp = matches!(address.base(), Some(BpSi | BpDi | Bp));
+        let segment = address.segment().unwrap_or(if based_on_bp {
+            SegmentRegister::SS
+        } else {
+            SegmentRegister::DS
+        });
+        (self.registers[segment], self.effective_offset(address))
+    }
+
+    /// Moves IP by a relative jump `offset`, staying within the code segment.
+    pub(crate) fn jump(&mut self, offset: i8) {
+        let target = self.ip().wrapping_add_signed(offset.into());
+        self.instruction_pointer = physical_address(self.registers[SegmentRegister::CS], target);
+    }
+
+    /// IP, the offset of the next instruction within the code segment. The instruction pointer
+    /// is the physical address CS and IP add up to, and IP wraps around at the end of the
+    /// segment rather than leaving it.
+    pub fn ip(&self) -> u16 {
+        let segment_start = usize::from(self.registers[SegmentRegister::CS]) << 4;
+        (self.instruction_pointer.wrapping_sub(segment_start) % MEMORY_SIZE) as u16
+    }
+
+    /// Continues at `segment:offset`, loading CS with `segment`.
+    pub fn jump_far(&mut self, segment: u16, offset: u16) {
+        self.registers[SegmentRegister::CS] = segment;
+        self.instruction_pointer = physical_address(segment, offset);
+    }
+
+    /// Pushes `value` onto the stack at SS:SP.
+    pub(crate) fn push(&mut self, value: u16) {
+        let stack_pointer = self.registers[RegisterWord::SP].wrapping_sub(2);
+        self.registers[RegisterWord::SP] = stack_pointer;
+        let stack_segment = self.registers[SegmentRegister::SS];
+        self.write_memory((stack_segment, stack_pointer), Width::Word, value);
+    }
+
+    /// Pops the word at SS:SP off the stack.
+    pub(crate) fn pop(&mut self) -> u16 {
+        let stack_pointer = self.registers[RegisterWord::SP];
+        self.registers[RegisterWord::SP] = stack_pointer.wrapping_add(2);
+        let stack_segment = self.registers[SegmentRegister::SS];
+        self.read_memory((stack_segment, stack_pointer), Width::Word)
+    }
+
+    /// Reads the value stored at `place`. Memory is read as a little endian word for
+    /// [`Width::Word`], otherwise only a single byte is read.
+    pub fn read(&mut self, place: Place, width: Width) -> u16 {
+        match place {
+            Place::Adress(address) => self.read_memory(self.effective_address(address), width),
+            _ => self.peek(place, width),
+        }
+    }
+
+    /// Reads from memory at `segment:offset`, counting and watching the access.
+    fn read_memory(&mut self, (segment, offset): (u16, u16), width: Width) -> u16 {
+        self.memory_counters.reads += 1;
+        self.memory_counters.unaligned_words += (width == Width::Word && offset % 2 == 1) as u64;
+        self.memory_counters.words += (width == Width::Word) as u64;
+        let value = self.peek_memory((segment, offset), width);
+        if !self.watchpoints.is_empty() {
+            let i = physical_address(segment, offset);
+            self.watch(AccessKind::Read, i, width, value, value);
+        }
+        value
+    }
+
+    /// Reads the value stored at `place` the same way as [`State::read`], but without it counting
+    /// as a memory access of the running program.
+    pub fn peek(&self, place: Place, width: Width) -> u16 {
+        match place {
+            Place::Byte(reg) => self.registers.byte(reg).into(),
+            Place::Word(reg) => self.registers[reg],
+            Place::Segment(reg) => self.registers[reg],
+            Place::Adress(address) => self.peek_memory(self.effective_address(address), width),
+        }
+    }
+
+    fn peek_memory(&self, (segment, offset): (u16, u16), width: Width) -> u16 {
+        let [low, high] = byte_addresses(segment, offset);
+        match width {
+            Width::Word => u16::from_le_bytes([self.memory[low], self.memory[high]]),
+            Width::Byte => self.memory[low].into(),
+        }
+    }
 
-        let mut memory = Vec::with_capacity(u16::MAX as usize);
-        self.program_end = match file.read_to_end(&mut memory) {
-            Err(error) => {
-                panic!("Failed to read the file with error: {error}");
+    /// Writes `value` into `place`, truncating it to a byte for [`Width::Byte`].
+    pub fn write(&mut self, place: Place, width: Width, value: u16) {
+        match place {
+            Place::Byte(reg) => self.registers.set_byte(reg, value as u8),
+            Place::Word(reg) => self.registers[reg] = value,
+            Place::Segment(reg) => self.registers[reg] = value,
+            Place::Adress(address) => {
+                self.write_memory(self.effective_address(address), width, value)
             }
-            Ok(bytes_read) => bytes_read,
+        }
+    }
+
+    /// Writes into memory at `segment:offset`, counting and watching the access.
+    fn write_memory(&mut self, (segment, offset): (u16, u16), width: Width, value: u16) {
+        self.memory_counters.writes += 1;
+        self.memory_counters.unaligned_words += (width == Width::Word && offset % 2 == 1) as u64;
+        self.memory_counters.words += (width == Width::Word) as u64;
+        let watched = !self.watchpoints.is_empty();
+        let old = watched.then(|| self.peek_memory((segment, offset), width));
+        let [low, high] = byte_addresses(segment, offset);
+        match width {
+            Width::Word => {
+                let [low_byte, high_byte] = value.to_le_bytes();
+                self.memory[low] = low_byte;
+                self.memory[high] = high_byte;
+            }
+            Width::Byte => self.memory[low] = value as u8,
+        }
+        self.check_code_write(low);
+        if width == Width::Word {
+            self.check_code_write(high);
+        }
+        if let Some(old) = old {
+            let new = self.peek_memory((segment, offset), width);
+            self.watch(AccessKind::Write, low, width, old, new);
+        }
+    }
+
+    /// Records a write into `address` if it holds code. Execution decodes the memory afresh for
+    /// every instruction, so the new bytes take effect even where the 8086 would run stale ones.
+    fn check_code_write(&mut self, address: usize) {
+        let kind = if self.executed[address] {
+            CodeKind::Executed
+        } else if self.pending.contains(&address) || self.pending.contains(&(address + MEMORY_SIZE))
+        {
+            CodeKind::Pending
+        } else {
+            return;
         };
-        self.memory[..memory.len()].copy_from_slice(memory.as_slice());
+        self.code_writes.push(CodeWrite {
+            instruction: self.instruction_address,
+            address,
+            kind,
+        });
+    }
+
+    fn watch(&mut self, kind: AccessKind, address: usize, width: Width, old: u16, new: u16) {
+        let access = MemoryAccess {
+            instruction: self.instruction_address,
+            kind,
+            address,
+            width,
+            old,
+            new,
+        };
+        if self.watchpoints.check(access) && self.stop.is_none() {
+            self.stop = Some(StopReason::Watchpoint(access));
+        }
     }
 }
 
-// TODO (matyas): add segment registers
 // TODO (matyas): add the rest of the flags
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Registers {
-    data_group: [SplitRegister; 4],
-    meta_group: [u16; 4],
+    /// AX, CX, DX, BX, SP, BP, SI and DI, in the order of their encoding.
+    general: [u16; 8],
+    segment_group: [u16; 4],
+    pub flag_carry: bool,
+    pub flag_parity: bool,
+    pub flag_auxiliary_carry: bool,
     pub flag_zero: bool,
     pub flag_sign: bool,
+    pub flag_overflow: bool,
 }
 
 impl Registers {
+    /// The value of the byte register `register`.
+    pub fn byte(&self, register: RegisterByte) -> u8 {
+        let (word, half) = Self::halves(register);
+        self.general[word].to_le_bytes()[half]
+    }
+
+    /// Stores `value` in the byte register `register`, leaving the other half of its word alone.
+    pub fn set_byte(&mut self, register: RegisterByte, value: u8) {
+        let (word, half) = Self::halves(register);
+        let mut bytes = self.general[word].to_le_bytes();
+        bytes[half] = value;
+        self.general[word] = u16::from_le_bytes(bytes);
+    }
+
+    /// The word register holding the byte register `register`, and which of its little endian
+    /// bytes it is. AL, CL, DL and BL are the low halves of AX, CX, DX and BX, while AH, CH, DH
+    /// and BH are their high halves, just like the encoding numbers them.
+    fn halves(register: RegisterByte) -> (usize, usize) {
+        let octal = register as usize;
+        (octal & 0b11, octal >> 2)
+    }
+
     pub fn flags_string(&self) -> String {
         format!(
-            "{}{}",
+            "{}{}{}{}{}{}",
+            if self.flag_carry { "C" } else { "" },
+            if self.flag_parity { "P" } else { "" },
+            if self.flag_auxiliary_carry { "A" } else { "" },
+            if self.flag_zero { "Z" } else { "" },
             if self.flag_sign { "S" } else { "" },
-            if self.flag_zero { "Z" } else { "" }
+            if self.flag_overflow { "O" } else { "" },
         )
     }
+
+    /// Sets exactly the flags whose letters of [`Registers::flags_string`] appear in `flags`.
+    pub fn set_flags_string(&mut self, flags: &str) {
+        self.flag_carry = flags.contains('C');
+        self.flag_parity = flags.contains('P');
+        self.flag_auxiliary_carry = flags.contains('A');
+        self.flag_zero = flags.contains('Z');
+        self.flag_sign = flags.contains('S');
+        self.flag_overflow = flags.contains('O');
+    }
+
+    /// The low byte of the flags register as `lahf` stores it: SF, ZF, AF, PF and CF in bits 7, 6,
+    /// 4, 2 and 0, with bit 1 always set.
+    pub fn status_byte(&self) -> u8 {
+        (self.flag_sign as u8) << 7
+            | (self.flag_zero as u8) << 6
+            | (self.flag_auxiliary_carry as u8) << 4
+            | (self.flag_parity as u8) << 2
+            | 0b10
+            | self.flag_carry as u8
+    }
+
+    /// Loads the flags from the whole flags register as `iret` pops it, with the overflow flag in
+    /// bit 11 on top of those of [`Registers::status_byte`]. The flags not simulated are ignored.
+    pub fn set_flags_word(&mut self, flags: u16) {
+        self.set_status_byte(flags as u8);
+        self.flag_overflow = flags & 1 << 11 > 0;
+    }
+
+    /// Loads the flags of [`Registers::status_byte`] from `status`, as `sahf` does.
+    pub fn set_status_byte(&mut self, status: u8) {
+        self.flag_sign = status & 1 << 7 > 0;
+        self.flag_zero = status & 1 << 6 > 0;
+        self.flag_auxiliary_carry = status & 1 << 4 > 0;
+        self.flag_parity = status & 1 << 2 > 0;
+        self.flag_carry = status & 1 > 0;
+    }
+
+    /// Sets the zero, sign and parity flags according to `result`.
+    fn set_result_flags(&mut self, result: u16, width: Width) {
+        self.flag_zero = result & width.mask() == 0;
+        self.flag_sign = result & width.sign_bit() > 0;
+        self.flag_parity = (result as u8).count_ones().is_multiple_of(2);
+    }
+
+    /// Performs the two operand arithmetic or logic operation `op` and updates all the status
+    /// flags. The caller decides whether the result is written back.
+    pub(crate) fn arithmetic(
+        &mut self,
+        op: ArithOp,
+        value: u16,
+        operand: u16,
+        width: Width,
+    ) -> u16 {
+        let sign_bit = width.sign_bit() as u32;
+        let mask = width.mask() as u32;
+        let value = value as u32 & mask;
+        let operand = operand as u32 & mask;
+
+        let result = match op {
+            ArithOp::Add | ArithOp::Adc => {
+                let carry = (op == ArithOp::Adc && self.flag_carry) as u32;
+                let result = value + operand + carry;
+                self.flag_carry = result > mask;
+                self.flag_auxiliary_carry = (value & 0xF) + (operand & 0xF) + carry > 0xF;
+                self.flag_overflow = (value ^ result) & (operand ^ result) & sign_bit > 0;
+                result
+            }
+            ArithOp::Sbb | ArithOp::Sub | ArithOp::Cmp => {
+                let borrow = (op == ArithOp::Sbb && self.flag_carry) as u32;
+                let result = value.wrapping_sub(operand).wrapping_sub(borrow);
+                self.flag_carry = value < operand + borrow;
+                self.flag_auxiliary_carry = (value & 0xF) < (operand & 0xF) + borrow;
+                self.flag_overflow = (value ^ operand) & (value ^ result) & sign_bit > 0;
+                result
+            }
+            ArithOp::Or | ArithOp::And | ArithOp::Xor => {
+                self.flag_carry = false;
+                self.flag_auxiliary_carry = false;
+                self.flag_overflow = false;
+                match op {
+                    ArithOp::Or => value | operand,
+                    ArithOp::And => value & operand,
+                    _ => value ^ operand,
+                }
+            }
+        } & mask;
+
+        self.set_result_flags(result as u16, width);
+        result as u16
+    }
+
+    /// Shifts or rotates `value` `count` times one bit at a time, the way the 8086 does it,
+    /// updating the carry and overflow flags along the way.
+    pub(crate) fn shift(&mut self, op: ShiftOp, value: u16, count: u8, width: Width) -> u16 {
+        if count == 0 {
+            return value;
+        }
+        let sign_bit = width.sign_bit();
+        let mask = width.mask();
+
+        let mut result = value & mask;
+        for _ in 0..count {
+            let msb = result & sign_bit > 0;
+            let lsb = result & 1 > 0;
+            result = match op {
+                ShiftOp::Rol => {
+                    self.flag_carry = msb;
+                    (result << 1) | lsb as u16
+                }
+                ShiftOp::Ror => {
+                    self.flag_carry = lsb;
+                    (result >> 1) | if lsb { sign_bit } else { 0 }
+                }
+                ShiftOp::Rcl => {
+                    let carry = self.flag_carry;
+                    self.flag_carry = msb;
+                    (result << 1) | carry as u16
+                }
+                ShiftOp::Rcr => {
+                    let carry = self.flag_carry;
+                    self.flag_carry = lsb;
+                    (result >> 1) | if carry { sign_bit } else { 0 }
+                }
+                ShiftOp::Shl | ShiftOp::Sal => {
+                    self.flag_carry = msb;
+                    result << 1
+                }
+                ShiftOp::Shr => {
+                    self.flag_carry = lsb;
+                    result >> 1
+                }
+                ShiftOp::Sar => {
+                    self.flag_carry = lsb;
+                    (result >> 1) | (result & sign_bit)
+                }
+            } & mask;
+        }
+
+        let msb = result & sign_bit > 0;
+        let below_msb = result & (sign_bit >> 1) > 0;
+        self.flag_overflow = match op {
+            ShiftOp::Rol | ShiftOp::Rcl | ShiftOp::Shl | ShiftOp::Sal => msb ^ self.flag_carry,
+            ShiftOp::Ror | ShiftOp::Rcr => msb ^ below_msb,
+            // SHR reports the sign of the original operand, SAR can never overflow
+            ShiftOp::Shr => count == 1 && value & sign_bit > 0,
+            ShiftOp::Sar => false,
+        };
+        // rotates leave the remaining flags alone
+        if !op.is_rotate() {
+            self.set_result_flags(result, width);
+        }
+        result
+    }
+
     pub fn print(&self) {
         use RegisterWord::*;
+        use SegmentRegister::*;
         println!(
             "AX: {}\nBX: {}\nCX: {}\nDX: {}\nSP: {}\nBP: {}\nSI: {}\nDI: {}",
             self[AX], self[BX], self[CX], self[DX], self[SP], self[BP], self[SI], self[DI]
+        );
+        println!(
+            "ES: {}\nCS: {}\nSS: {}\nDS: {}",
+            self[ES], self[CS], self[SS], self[DS]
         )
     }
 }
@@ -168,61 +702,26 @@ impl Index<RegisterWord> for Registers {
     type Output = u16;
 
     fn index(&self, index: RegisterWord) -> &Self::Output {
-        match index {
-            RegisterWord::AX => unsafe { &self.data_group[0].word },
-            RegisterWord::CX => unsafe { &self.data_group[1].word },
-            RegisterWord::DX => unsafe { &self.data_group[2].word },
-            RegisterWord::BX => unsafe { &self.data_group[3].word },
-            RegisterWord::SP => &self.meta_group[0],
-            RegisterWord::BP => &self.meta_group[1],
-            RegisterWord::SI => &self.meta_group[2],
-            RegisterWord::DI => &self.meta_group[3],
-        }
+        &self.general[index as usize]
     }
 }
 
 impl IndexMut<RegisterWord> for Registers {
     fn index_mut(&mut self, index: RegisterWord) -> &mut Self::Output {
-        match index {
-            RegisterWord::AX => unsafe { &mut self.data_group[0].word },
-            RegisterWord::CX => unsafe { &mut self.data_group[1].word },
-            RegisterWord::DX => unsafe { &mut self.data_group[2].word },
-            RegisterWord::BX => unsafe { &mut self.data_group[3].word },
-            RegisterWord::SP => &mut self.meta_group[0],
-            RegisterWord::BP => &mut self.meta_group[1],
-            RegisterWord::SI => &mut self.meta_group[2],
-            RegisterWord::DI => &mut self.meta_group[3],
-        }
-    }
-}
-
-impl Index<RegisterByte> for Registers {
-    type Output = u8;
-
-    fn index(&self, index: RegisterByte) -> &Self::Output {
-        match index {
-            RegisterByte::AH => unsafe { &self.data_group[0].byte.h },
-            RegisterByte::AL => unsafe { &self.data_group[0].byte.l },
-            RegisterByte::BH => unsafe { &self.data_group[1].byte.h },
-            RegisterByte::BL => unsafe { &self.data_group[1].byte.l },
-            RegisterByte::CH => unsafe { &self.data_group[2].byte.h },
-            RegisterByte::CL => unsafe { &self.data_group[2].byte.l },
-            RegisterByte::DH => unsafe { &self.data_group[3].byte.h },
-            RegisterByte::DL => unsafe { &self.data_group[3].byte.l },
-        }
-    }
-}
-impl IndexMut<RegisterByte> for Registers {
-    fn index_mut(&mut self, index: RegisterByte) -> &mut Self::Output {
-        match index {
-            RegisterByte::AH => unsafe { &mut self.data_group[0].byte.h },
-            RegisterByte::AL => unsafe { &mut self.data_group[0].byte.l },
-            RegisterByte::BH => unsafe { &mut self.data_group[1].byte.h },
-            RegisterByte::BL => unsafe { &mut self.data_group[1].byte.l },
-            RegisterByte::CH => unsafe { &mut self.data_group[2].byte.h },
-            RegisterByte::CL => unsafe { &mut self.data_group[2].byte.l },
-            RegisterByte::DH => unsafe { &mut self.data_group[3].byte.h },
-            RegisterByte::DL => unsafe { &mut self.data_group[3].byte.l },
-        }
+        &mut self.general[index as usize]
+    }
+}
+
+impl Index<SegmentRegister> for Registers {
+    type Output = u16;
+
+    fn index(&self, index: SegmentRegister) -> &Self::Output {
+        &self.segment_group[index as usize]
+    }
+}
+
+impl IndexMut<SegmentRegister> for Registers {
+    fn index_mut(&mut self, index: SegmentRegister) -> &mut Self::Output {
+        &mut self.segment_group[index as usize]
     }
 }
@@ -0,0 +1,469 @@
+//! Rendering of decoded instructions in the syntax of different assemblers.
+//!
+//! The `Display` impls of [`Instruction`], [`Place`] and [`EffectiveAdress`] use the default
+//! [`Style`], which is the NASM syntax the course listings are written in.
+
+use std::fmt::{self, Display, Write};
+
+use crate::{Branch, EffectiveAdress, Instruction, Place, Port, Repeat, Width};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    Nasm,
+    /// Intel syntax as MASM spells it, with `word ptr` sizes and `h` suffixed hexadecimals.
+    Intel,
+    /// AT&T syntax as the GNU assembler spells it, with the source operand first.
+    Att,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Case {
+    Lower,
+    Upper,
+}
+
+/// Base numbers are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    Decimal,
+    Hexadecimal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    pub syntax: Syntax,
+    pub mnemonics: Case,
+    pub registers: Case,
+    pub radix: Radix,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            syntax: Syntax::Nasm,
+            mnemonics: Case::Lower,
+            registers: Case::Upper,
+            radix: Radix::Decimal,
+        }
+    }
+}
+
+/// An operand of an instruction, independent of the syntax it is written in.
+enum Operand {
+    Place(Place),
+    Immediate(i16, Width),
+    Unsigned(u16),
+    Port(Port),
+    /// Relative to the end of the instruction, which is `length` bytes long.
+    Relative {
+        length: u8,
+        offset: i16,
+    },
+    Far {
+        segment: u16,
+        offset: u16,
+    },
+}
+
+/// Annotation spelling out what the operands alone leave ambiguous.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Keyword {
+    Size(Width),
+    Short,
+    Near,
+    /// Far pointer in memory.
+    Far,
+}
+
+/// An instruction taken apart into what every syntax writes, with operands in Intel order.
+struct Parts {
+    repeat: Option<Repeat>,
+    mnemonic: &'static str,
+    operands: Vec<Operand>,
+    /// Keyword and the index of the operand it goes in front of.
+    keyword: Option<(Keyword, usize)>,
+    /// Whether the operand is the address of the code to branch to, rather than the code itself.
+    indirect: bool,
+    far: bool,
+}
+
+impl Parts {
+    fn new(mnemonic: &'static str, operands: Vec<Operand>) -> Self {
+        Self {
+            repeat: None,
+            mnemonic,
+            operands,
+            keyword: None,
+            indirect: false,
+            far: false,
+        }
+    }
+
+    /// Spells out `width` in front of `target` when it's a memory operand.
+    fn sized(mut self, width: Width, target: &Place) -> Self {
+        if let Place::Adress(_) = target {
+            self.keyword = Some((Keyword::Size(width), 0));
+        }
+        self
+    }
+
+    fn branch(branch: &Branch, mnemonic: &'static str) -> Self {
+        let (operand, keyword, indirect, far) = match *branch {
+            Branch::Short(offset) => (
+                Operand::Relative {
+                    length: 2,
+                    offset: offset.into(),
+                },
+                Some(Keyword::Short),
+                false,
+                false,
+            ),
+            Branch::Near(offset) => (
+                Operand::Relative { length: 3, offset },
+                Some(Keyword::Near),
+                false,
+                false,
+            ),
+            Branch::Indirect(target) => (
+                Operand::Place(target),
+                matches!(target, Place::Adress(_)).then_some(Keyword::Size(Width::Word)),
+                true,
+                false,
+            ),
+            Branch::Far { segment, offset } => {
+                (Operand::Far { segment, offset }, None, false, true)
+            }
+            Branch::FarIndirect(target) => (Operand::Place(target), Some(Keyword::Far), true, true),
+        };
+        Self {
+            keyword: keyword.map(|keyword| (keyword, 0)),
+            indirect,
+            far,
+            ..Self::new(mnemonic, vec![operand])
+        }
+    }
+}
+
+fn parts(instruction: &Instruction) -> Parts {
+    use Operand::*;
+    let mnemonic = instruction.mnemonic();
+    match *instruction {
+        Instruction::Arithmetic { target, source, .. }
+        | Instruction::Test { target, source }
+        | Instruction::Xchg { target, source }
+        | Instruction::Load { target, source, .. }
+        | Instruction::Mov { target, source } => {
+            Parts::new(mnemonic, vec![Place(target), Place(source)])
+        }
+        Instruction::ArithmeticImmediate {
+            width,
+            target,
+            immediate,
+            ..
+        }
+        | Instruction::TestImmediate {
+            width,
+            target,
+            immediate,
+        }
+        | Instruction::MovImmediate {
+            width,
+            target,
+            immediate,
+        } => Parts::new(mnemonic, vec![Place(target), Immediate(immediate, width)])
+            .sized(width, &target),
+        Instruction::Jump { offset, .. } | Instruction::Loop { offset, .. } => Parts::new(
+            mnemonic,
+            vec![Relative {
+                length: 2,
+                offset: offset.into(),
+            }],
+        ),
+        Instruction::Shift {
+            width,
+            target,
+            by_cl,
+            ..
+        } => {
+            let count = if by_cl {
+                Place(crate::Place::Byte(crate::RegisterByte::CL))
+            } else {
+                Unsigned(1)
+            };
+            Parts::new(mnemonic, vec![Place(target), count]).sized(width, &target)
+        }
+        Instruction::Unary { width, target, .. } => {
+            Parts::new(mnemonic, vec![Place(target)]).sized(width, &target)
+        }
+        Instruction::Push { target } | Instruction::Pop { target } => {
+            Parts::new(mnemonic, vec![Place(target)]).sized(Width::Word, &target)
+        }
+        Instruction::StringOperation { repeat, .. } => Parts {
+            repeat,
+            ..Parts::new(mnemonic, Vec::new())
+        },
+        Instruction::Call(ref branch) | Instruction::Jmp(ref branch) => {
+            Parts::branch(branch, mnemonic)
+        }
+        Instruction::Return { far, pop } => Parts {
+            far,
+            ..Parts::new(mnemonic, pop.map(Unsigned).into_iter().collect())
+        },
+        Instruction::Interrupt(vector) => Parts::new(mnemonic, vec![Unsigned(vector.into())]),
+        Instruction::In { width, port } => Parts::new(
+            mnemonic,
+            vec![Place(crate::Place::register(width, 0)), Port(port)],
+        ),
+        Instruction::Out { width, port } => Parts::new(
+            mnemonic,
+            vec![Port(port), Place(crate::Place::register(width, 0))],
+        ),
+        Instruction::Implied(_) | Instruction::Unrecognized(_) => Parts::new(mnemonic, Vec::new()),
+    }
+}
+
+impl Style {
+    /// Directive that has to come before the instructions for them to assemble as 16 bit code.
+    pub fn header(&self) -> &'static str {
+        match self.syntax {
+            Syntax::Nasm => "bits 16",
+            Syntax::Intel => ".8086",
+            Syntax::Att => ".code16",
+        }
+    }
+
+    pub fn instruction<'a>(&'a self, instruction: &'a Instruction) -> impl Display + 'a {
+        Styled(self, instruction)
+    }
+
+    pub fn place<'a>(&'a self, place: &'a Place) -> impl Display + 'a {
+        Styled(self, place)
+    }
+
+    pub(crate) fn write_instruction(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        instruction: &Instruction,
+    ) -> fmt::Result {
+        if let Instruction::Unrecognized(byte) = instruction {
+            return write!(f, "\x1B[1m\x1B[31m{byte:2x}\x1B[0m unrecognized");
+        }
+        let parts = parts(instruction);
+        if let Some(repeat) = parts.repeat {
+            self.write_mnemonic(f, repeat.mnemonic())?;
+            f.write_char(' ')?;
+        }
+        if self.syntax == Syntax::Att {
+            return self.write_att(f, parts);
+        }
+
+        self.write_mnemonic(f, parts.mnemonic)?;
+        for (index, operand) in parts.operands.iter().enumerate() {
+            f.write_str(if index == 0 { " " } else { ", " })?;
+            if let Some((keyword, at)) = parts.keyword {
+                // NASM puts the size of moved immediates on the immediate, like the listings do
+                let at = match (self.syntax, instruction) {
+                    (Syntax::Nasm, Instruction::MovImmediate { .. }) => 1,
+                    _ => at,
+                };
+                if at == index {
+                    self.write_keyword(f, keyword)?;
+                }
+            }
+            self.write_operand(f, operand)?;
+        }
+        Ok(())
+    }
+
+    fn write_att(&self, f: &mut fmt::Formatter<'_>, parts: Parts) -> fmt::Result {
+        let mnemonic = match parts.mnemonic {
+            "retf" => "lret",
+            mnemonic if parts.far => &format!("l{mnemonic}"),
+            mnemonic => mnemonic,
+        };
+        self.write_mnemonic(f, mnemonic)?;
+        if let Some((Keyword::Size(width), _)) = parts.keyword {
+            if !parts.indirect {
+                self.write_mnemonic(f, if width == Width::Byte { "b" } else { "w" })?;
+            }
+        }
+        for (index, operand) in parts.operands.iter().rev().enumerate() {
+            f.write_str(if index == 0 { " " } else { ", " })?;
+            if parts.indirect {
+                f.write_char('*')?;
+            }
+            self.write_operand(f, operand)?;
+        }
+        Ok(())
+    }
+
+    fn write_mnemonic(&self, f: &mut fmt::Formatter<'_>, mnemonic: &str) -> fmt::Result {
+        match self.mnemonics {
+            Case::Lower => f.write_str(mnemonic),
+            Case::Upper => f.write_str(&mnemonic.to_uppercase()),
+        }
+    }
+
+    fn write_keyword(&self, f: &mut fmt::Formatter<'_>, keyword: Keyword) -> fmt::Result {
+        let keyword = match (self.syntax, keyword) {
+            (_, Keyword::Short) => "short",
+            (Syntax::Intel, Keyword::Size(Width::Byte)) => "byte ptr",
+            (Syntax::Intel, Keyword::Size(Width::Word)) => "word ptr",
+            (Syntax::Intel, Keyword::Near) => "near ptr",
+            (Syntax::Intel, Keyword::Far) => "dword ptr",
+            (_, Keyword::Size(Width::Byte)) => "byte",
+            (_, Keyword::Size(Width::Word)) => "word",
+            (_, Keyword::Near) => "near",
+            (_, Keyword::Far) => "far",
+        };
+        self.write_mnemonic(f, keyword)?;
+        f.write_char(' ')
+    }
+
+    fn write_register(&self, f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+        if self.syntax == Syntax::Att {
+            f.write_char('%')?;
+        }
+        match self.registers {
+            Case::Lower => f.write_str(&name.to_lowercase()),
+            Case::Upper => f.write_str(name),
+        }
+    }
+
+    fn write_operand(&self, f: &mut fmt::Formatter<'_>, operand: &Operand) -> fmt::Result {
+        let immediate = if self.syntax == Syntax::Att { "$" } else { "" };
+        match *operand {
+            Operand::Place(place) => self.write_place(f, &place),
+            Operand::Immediate(value, width) => {
+                f.write_str(immediate)?;
+                match self.radix {
+                    Radix::Decimal => write!(f, "{value}"),
+                    Radix::Hexadecimal => {
+                        self.write_number(f, (value as u16 & width.mask()).into())
+                    }
+                }
+            }
+            Operand::Unsigned(value) => {
+                f.write_str(immediate)?;
+                self.write_number(f, value.into())
+            }
+            Operand::Port(Port::Immediate(port)) => {
+                f.write_str(immediate)?;
+                self.write_number(f, port.into())
+            }
+            Operand::Port(Port::Dx) if self.syntax == Syntax::Att => {
+                f.write_char('(')?;
+                self.write_register(f, "DX")?;
+                f.write_char(')')
+            }
+            Operand::Port(Port::Dx) => self.write_register(f, "DX"),
+            Operand::Relative { length, offset } => {
+                let here = if self.syntax == Syntax::Att { '.' } else { '$' };
+                write!(f, "{here}+{length}{}", if offset < 0 { '-' } else { '+' })?;
+                self.write_number(f, offset.unsigned_abs().into())
+            }
+            Operand::Far { segment, offset } => {
+                let separator = if self.syntax == Syntax::Att {
+                    ", "
+                } else {
+                    ":"
+                };
+                f.write_str(immediate)?;
+                self.write_number(f, segment.into())?;
+                f.write_str(separator)?;
+                f.write_str(immediate)?;
+                self.write_number(f, offset.into())
+            }
+        }
+    }
+
+    pub(crate) fn write_place(&self, f: &mut fmt::Formatter<'_>, place: &Place) -> fmt::Result {
+        match place {
+            Place::Byte(reg) => self.write_register(f, &format!("{reg:?}")),
+            Place::Word(reg) => self.write_register(f, &format!("{reg:?}")),
+            Place::Segment(reg) => self.write_register(f, &format!("{reg:?}")),
+            Place::Adress(address) => self.write_address(f, address),
+        }
+    }
+
+    pub(crate) fn write_address(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        address: &EffectiveAdress,
+    ) -> fmt::Result {
+        if let Some(segment) = address.segment {
+            self.write_register(f, &format!("{segment:?}"))?;
+            f.write_char(':')?;
+        }
+        if address.is_direct() {
+            let (open, close) = match self.syntax {
+                Syntax::Att => ("", ""),
+                _ => ("[", "]"),
+            };
+            f.write_str(open)?;
+            self.write_number(f, (address.displacement as u16).into())?;
+            return f.write_str(close);
+        }
+
+        let registers = EffectiveAdress::ADRESS_CALCULATION[address.index as usize];
+        let displacement = address.displacement;
+        if self.syntax == Syntax::Att {
+            if displacement < 0 {
+                f.write_char('-')?;
+            }
+            if displacement != 0 {
+                self.write_number(f, displacement.unsigned_abs().into())?;
+            }
+            f.write_char('(')?;
+            for (index, register) in registers.split(" + ").enumerate() {
+                if index > 0 {
+                    f.write_char(',')?;
+                }
+                self.write_register(f, register)?;
+            }
+            return f.write_char(')');
+        }
+
+        f.write_char('[')?;
+        for (index, register) in registers.split(" + ").enumerate() {
+            if index > 0 {
+                f.write_str(" + ")?;
+            }
+            self.write_register(f, register)?;
+        }
+        if displacement != 0 {
+            f.write_str(if displacement < 0 { " - " } else { " + " })?;
+            self.write_number(f, displacement.unsigned_abs().into())?;
+        }
+        f.write_char(']')
+    }
+
+    fn write_number(&self, f: &mut fmt::Formatter<'_>, value: u32) -> fmt::Result {
+        match (self.radix, self.syntax) {
+            (Radix::Decimal, _) => write!(f, "{value}"),
+            (Radix::Hexadecimal, Syntax::Intel) => {
+                // MASM takes anything starting with a letter for a name
+                let digits = format!("{value:X}");
+                if digits.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                    f.write_char('0')?;
+                }
+                write!(f, "{digits}h")
+            }
+            (Radix::Hexadecimal, _) => write!(f, "{value:#x}"),
+        }
+    }
+}
+
+struct Styled<'a, T>(&'a Style, &'a T);
+
+impl Display for Styled<'_, Instruction> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.write_instruction(f, self.1)
+    }
+}
+
+impl Display for Styled<'_, Place> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.write_place(f, self.1)
+    }
+}
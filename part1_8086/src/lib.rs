@@ -1,10 +1,22 @@
 use std::{fmt::Display, io::Read};
 
 use decode::advance_by;
-use exec::State;
+use exec::{Registers, State};
+use format::Style;
 
+pub mod cfg;
+pub mod cycles;
 pub mod decode;
+pub mod encode;
 pub mod exec;
+pub mod format;
+pub mod listing;
+pub mod ports;
+pub mod prefetch;
+pub mod setup;
+pub mod stats;
+pub mod tui;
+pub mod watch;
 
 #[cfg(test)]
 mod tests;
@@ -23,44 +35,94 @@ pub fn read_listing(listing_path: &str) -> (Vec<u8>, usize) {
     (memory, length)
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     Arithmetic {
-        op: u8,
+        op: ArithOp,
         target: Place,
         source: Place,
     },
     ArithmeticImmediate {
-        op: u8,
-        target: Place,
-        immediate: u16,
-    },
-    ArithmeticImmediateToMemory {
-        word_mode: bool,
-        op: u8,
+        op: ArithOp,
+        width: Width,
         target: Place,
-        immediate: u16,
+        immediate: i16,
     },
     Jump {
-        marker: u8,
+        condition: JumpCondition,
         offset: i8,
     },
     Loop {
-        marker: u8,
+        kind: LoopKind,
         offset: i8,
     },
-    Mov {
+    Shift {
+        op: ShiftOp,
+        width: Width,
+        target: Place,
+        by_cl: bool,
+    },
+    Test {
         target: Place,
         source: Place,
     },
-    MovImmediate {
+    TestImmediate {
+        width: Width,
+        target: Place,
+        immediate: i16,
+    },
+    Unary {
+        op: UnaryOp,
+        width: Width,
+        target: Place,
+    },
+    Push {
+        target: Place,
+    },
+    Pop {
+        target: Place,
+    },
+    Xchg {
         target: Place,
-        immediate: u16,
+        source: Place,
     },
-    MovImmediateToMemory {
-        word_mode: bool,
+    /// `lea`, `lds` and `les`, loading the address or the far pointer stored at `source`.
+    Load {
+        op: LoadOp,
         target: Place,
-        immediate: u16,
+        source: Place,
+    },
+    StringOperation {
+        op: StringOp,
+        width: Width,
+        repeat: Option<Repeat>,
+    },
+    Call(Branch),
+    /// Unconditional jump.
+    Jmp(Branch),
+    Return {
+        far: bool,
+        /// Bytes of arguments to pop off the stack after the return address.
+        pop: Option<u16>,
+    },
+    Interrupt(u8),
+    Implied(ImpliedOp),
+    In {
+        width: Width,
+        port: Port,
+    },
+    Out {
+        width: Width,
+        port: Port,
+    },
+    Mov {
+        target: Place,
+        source: Place,
+    },
+    MovImmediate {
+        width: Width,
+        target: Place,
+        immediate: i16,
     },
     Unrecognized(u8),
 }
@@ -69,294 +131,897 @@ impl Instruction {
         match self {
             Instruction::Arithmetic { target, .. }
             | Instruction::ArithmeticImmediate { target, .. }
-            | Instruction::ArithmeticImmediateToMemory { target, .. }
             | Instruction::Mov { target, .. }
             | Instruction::MovImmediate { target, .. }
-            | Instruction::MovImmediateToMemory { target, .. } => Some(*target),
+            | Instruction::Shift { target, .. }
+            | Instruction::Unary { target, .. }
+            | Instruction::Pop { target }
+            | Instruction::Xchg { target, .. }
+            | Instruction::Load { target, .. } => Some(*target),
+            Instruction::In { width, .. } => Some(Place::register(*width, 0)),
             _ => None,
         }
     }
 
-    pub const MATH: [&'static str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
-    pub const JUMP: [&'static str; 8] = ["o", "b", "z", "be", "s", "p", "l", "le"];
-    pub const LOOP: [&'static str; 4] = ["loopnz", "loopz", "loop", "jcxz"];
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Arithmetic { op, .. } | Instruction::ArithmeticImmediate { op, .. } => {
+                op.mnemonic()
+            }
+            Instruction::Jump { condition, .. } => condition.mnemonic(),
+            Instruction::Loop { kind, .. } => kind.mnemonic(),
+            Instruction::Shift { op, .. } => op.mnemonic(),
+            Instruction::Test { .. } | Instruction::TestImmediate { .. } => "test",
+            Instruction::Unary { op, .. } => op.mnemonic(),
+            Instruction::Push { .. } => "push",
+            Instruction::Pop { .. } => "pop",
+            Instruction::Xchg { .. } => "xchg",
+            Instruction::Load { op, .. } => op.mnemonic(),
+            Instruction::StringOperation { op, width, .. } => op.mnemonic(*width),
+            Instruction::Call(_) => "call",
+            Instruction::Jmp(_) => "jmp",
+            Instruction::Return { far: false, .. } => "ret",
+            Instruction::Return { far: true, .. } => "retf",
+            Instruction::Interrupt(_) => "int",
+            Instruction::Implied(op) => op.mnemonic(),
+            Instruction::In { .. } => "in",
+            Instruction::Out { .. } => "out",
+            Instruction::Mov { .. } | Instruction::MovImmediate { .. } => "mov",
+            Instruction::Unrecognized(_) => "unrecognized",
+        }
+    }
+
+    /// The width of the operands the instruction works with.
+    fn width(&self) -> Width {
+        match self {
+            Instruction::Arithmetic { target, source, .. }
+            | Instruction::Mov { target, source }
+            | Instruction::Test { target, source }
+            | Instruction::Xchg { target, source } => {
+                let is_word = |place| matches!(place, &Place::Word(_) | &Place::Segment(_));
+                if is_word(target) || is_word(source) {
+                    Width::Word
+                } else {
+                    Width::Byte
+                }
+            }
+            Instruction::ArithmeticImmediate { width, .. }
+            | Instruction::MovImmediate { width, .. }
+            | Instruction::Shift { width, .. }
+            | Instruction::TestImmediate { width, .. }
+            | Instruction::Unary { width, .. }
+            | Instruction::StringOperation { width, .. }
+            | Instruction::In { width, .. }
+            | Instruction::Out { width, .. } => *width,
+            _ => Width::Word,
+        }
+    }
+
+    /// Whether the simulator can execute the instruction, as opposed to only decoding it.
+    pub fn is_simulated(&self) -> bool {
+        match self {
+            Instruction::Arithmetic { .. }
+            | Instruction::ArithmeticImmediate { .. }
+            | Instruction::Jump { .. }
+            | Instruction::Loop { .. }
+            | Instruction::Shift { .. }
+            | Instruction::Test { .. }
+            | Instruction::TestImmediate { .. }
+            | Instruction::In { .. }
+            | Instruction::Out { .. }
+            | Instruction::Mov { .. }
+            | Instruction::MovImmediate { .. }
+            | Instruction::Xchg { .. }
+            | Instruction::Load { .. }
+            | Instruction::Return { far: true, .. } => true,
+            Instruction::Call(branch) | Instruction::Jmp(branch) => matches!(
+                branch,
+                Branch::Far { .. } | Branch::FarIndirect(Place::Adress(_))
+            ),
+            Instruction::Implied(op) => matches!(
+                op,
+                ImpliedOp::Xlat
+                    | ImpliedOp::Lahf
+                    | ImpliedOp::Sahf
+                    | ImpliedOp::Cbw
+                    | ImpliedOp::Cwd
+                    | ImpliedOp::Iret
+            ),
+            Instruction::Unary { op, .. } => {
+                matches!(
+                    op,
+                    UnaryOp::Not | UnaryOp::Neg | UnaryOp::Inc | UnaryOp::Dec
+                )
+            }
+            _ => false,
+        }
+    }
+
+    /// The memory operand of the instruction, if it has one. No instruction has more than one.
+    pub(crate) fn memory_operand_mut(&mut self) -> Option<&mut EffectiveAdress> {
+        match self {
+            Instruction::Arithmetic { target, source, .. }
+            | Instruction::Mov { target, source }
+            | Instruction::Test { target, source }
+            | Instruction::Xchg { target, source }
+            | Instruction::Load { target, source, .. } => {
+                target.address_mut().or_else(|| source.address_mut())
+            }
+            Instruction::ArithmeticImmediate { target, .. }
+            | Instruction::Shift { target, .. }
+            | Instruction::TestImmediate { target, .. }
+            | Instruction::Unary { target, .. }
+            | Instruction::Push { target }
+            | Instruction::Pop { target }
+            | Instruction::MovImmediate { target, .. }
+            | Instruction::Call(Branch::Indirect(target) | Branch::FarIndirect(target))
+            | Instruction::Jmp(Branch::Indirect(target) | Branch::FarIndirect(target)) => {
+                target.address_mut()
+            }
+            _ => None,
+        }
+    }
 
-    #[allow(unused_variables)]
     fn run(self, state: &mut State) {
-        use RegisterWord::*;
         match self {
-            Instruction::MovImmediate { target, immediate } => match target {
-                Place::Byte(reg) => state.registers[reg] = immediate as u8,
-                Place::Word(reg) => state.registers[reg] = immediate,
-                Place::Adress(_) => todo!(),
-            },
             Instruction::Arithmetic { op, target, source } => {
-                let result = match (Self::MATH[op as usize], target, source) {
-                    // (Place::Byte(_), Place::Byte(_)) => todo!(),
-                    // (Place::Byte(_), Place::Word(_)) => todo!(),
-                    // (Place::Byte(_), Place::Adress(_)) => todo!(),
-                    // (Place::Word(_), Place::Byte(_)) => todo!(),
-                    ("add", Place::Word(target), Place::Word(source)) => {
-                        state.registers[target] =
-                            state.registers[target].wrapping_add(state.registers[source]);
-                        state.registers[target]
-                    }
-                    ("add", Place::Word(target), Place::Adress(source)) => {
-                        match source.mode {
-                            Mode::EffectiveAdress => {
-                                let i: usize = match source.index {
-                                    0b110 => source.displacement,
-                                    0b010 => state.registers[BP] + state.registers[SI],
-                                    _ => todo!(),
-                                }
-                                .into();
-                                let hi = state.memory[i];
-                                let lo = state.memory[i + 1];
-                                let value = u16::from_be_bytes([hi, lo]);
-                                state.registers[target] = value;
-                            }
-                            Mode::EffectiveAdressByte => todo!(),
-                            Mode::EffectiveAdressWord => todo!(),
-                            Mode::RegisterToRegister => todo!(),
-                        };
-                        // registers[target] = registers[target].wrapping_add(registers[source]);
-                        state.registers[target]
-                    }
-                    ("sub", Place::Word(target), Place::Word(source)) => {
-                        state.registers[target] =
-                            state.registers[target].wrapping_sub(state.registers[source]);
-                        state.registers[target]
-                    }
-                    ("cmp", Place::Word(target), Place::Word(source)) => {
-                        state.registers[target].wrapping_sub(state.registers[source])
-                    }
-                    // (Place::Word(_), Place::Adress(_)) => todo!(),
-                    // (Place::Adress(_), Place::Byte(_)) => todo!(),
-                    // (Place::Adress(_), Place::Word(_)) => todo!(),
-                    // (Place::Adress(_), Place::Adress(_)) => todo!(),
-                    _ => todo!(),
-                };
-                state.registers.flag_sign = (result & 0x8000) > 0;
-                state.registers.flag_zero = result == 0;
+                let width = self.width();
+                let value = state.read(target, width);
+                let operand = state.read(source, width);
+                let result = state.registers.arithmetic(op, value, operand, width);
+                if op != ArithOp::Cmp {
+                    state.write(target, width, result);
+                }
             }
             Instruction::ArithmeticImmediate {
                 op,
-                target: register,
-                immediate,
-            } => todo!(),
-            Instruction::ArithmeticImmediateToMemory {
-                word_mode,
-                op,
+                width,
                 target,
                 immediate,
             } => {
-                let result = match (Self::MATH[op as usize], target) {
-                    ("add", Place::Word(target)) => {
-                        state.registers[target] = state.registers[target].wrapping_add(immediate);
-                        state.registers[target]
-                    }
-                    ("sub", Place::Word(target)) => {
-                        state.registers[target] = state.registers[target].wrapping_sub(immediate);
-                        state.registers[target]
-                    }
-                    ("cmp", Place::Word(target)) => state.registers[target].wrapping_sub(immediate),
-                    _ => todo!(),
-                };
-                state.registers.flag_sign = (result & 0x8000) > 0;
-                state.registers.flag_zero = result == 0;
-            }
-            Instruction::Jump { marker, offset } => {
-                let negated = (marker & 1) > 0;
-                let kind = Self::JUMP[((marker >> 1) & 0b111) as usize];
-                match kind {
-                    "o" => todo!(),
-                    "b" => todo!(),
-                    "z" => {
-                        if state.registers.flag_zero ^ negated {
-                            if offset.is_positive() {
-                                state.instruction_pointer += offset as usize;
-                            } else {
-                                state.instruction_pointer -= offset.abs() as usize;
-                            }
-                        }
-                    }
-                    "be" => todo!(),
-                    "s" => todo!(),
-                    "p" => todo!(),
-                    "l" => todo!(),
-                    "le" => todo!(),
-                    _ => unreachable!(),
+                let value = state.read(target, width);
+                let result = state
+                    .registers
+                    .arithmetic(op, value, immediate as u16, width);
+                if op != ArithOp::Cmp {
+                    state.write(target, width, result);
                 }
-                // todo!()
             }
-            Instruction::Loop { marker, offset } => todo!(),
-            Instruction::Mov { target, source } => match (target, source) {
-                (Place::Byte(target), Place::Byte(source)) => todo!(),
-                (Place::Byte(target), Place::Word(source)) => todo!(),
-                (Place::Byte(target), Place::Adress(source)) => todo!(),
-                (Place::Word(target), Place::Byte(source)) => todo!(),
-                (Place::Word(target), Place::Word(source)) => {
-                    state.registers[target] = state.registers[source]
+            Instruction::Jump { condition, offset } => {
+                if condition.holds(&state.registers) {
+                    state.jump(offset);
                 }
-                (Place::Word(target), Place::Adress(source)) => match source.mode {
-                    Mode::EffectiveAdress => {
-                        let i: usize = match source.index {
-                            0b110 => source.displacement,
-                            0b010 => state.registers[BP] + state.registers[SI],
-                            _ => todo!(),
-                        }
-                        .into();
-                        let hi = state.memory[i];
-                        let lo = state.memory[i + 1];
-                        let value = u16::from_be_bytes([hi, lo]);
-                        state.registers[target] = value;
-                    }
-                    Mode::EffectiveAdressByte => todo!(),
-                    Mode::EffectiveAdressWord => todo!(),
-                    Mode::RegisterToRegister => todo!(),
-                },
-                (Place::Adress(target), Place::Byte(source)) => todo!(),
-                (Place::Adress(target), Place::Word(source)) => {
-                    let value = state.registers[source];
-                    let bytes = value.to_be_bytes();
-                    match target.mode {
-                        Mode::EffectiveAdress => {
-                            let i: usize = match target.index {
-                                0b010 => state.registers[BP] + state.registers[SI],
-                                _ => todo!(),
-                            }
-                            .into();
-                            state.memory[i..(i + 2)].copy_from_slice(&bytes);
-                        }
-                        Mode::EffectiveAdressByte => {
-                            let i: usize = match target.index {
-                                0b110 => {
-                                    // print!("      uwu      ");
-                                    state.registers[BP]
-                                }
-                                _ => todo!(),
-                            }
-                            .into();
-                            state.memory[i] = value as u8;
-                        }
-                        Mode::EffectiveAdressWord => todo!(),
-                        Mode::RegisterToRegister => todo!(),
-                    }
-                }
-                (Place::Adress(target), Place::Adress(source)) => todo!(),
-            },
-            Instruction::MovImmediateToMemory {
-                word_mode,
-                target,
-                immediate,
-            } => match target {
-                Place::Byte(_) => todo!(),
-                Place::Word(_) => todo!(),
-                Place::Adress(address) => {
-                    let bytes = immediate.to_be_bytes();
-                    let i: usize = match address.mode {
-                        Mode::EffectiveAdress => match address.index {
-                            0b110 => address.displacement,
-                            _ => todo!(),
-                        },
-                        Mode::EffectiveAdressByte => {
-                            (match address.index {
-                                0b110 => state.registers[BP],
-                                0b111 => state.registers[BX],
-                                _ => todo!(),
-                            } + address.displacement)
+            }
+            Instruction::Loop { kind, offset } => {
+                let taken = if kind == LoopKind::Jcxz {
+                    state.registers[RegisterWord::CX] == 0
+                } else {
+                    let count = state.registers[RegisterWord::CX].wrapping_sub(1);
+                    state.registers[RegisterWord::CX] = count;
+                    count != 0
+                        && match kind {
+                            LoopKind::Loopnz => !state.registers.flag_zero,
+                            LoopKind::Loopz => state.registers.flag_zero,
+                            _ => true,
                         }
-                        Mode::EffectiveAdressWord => todo!(),
-                        Mode::RegisterToRegister => todo!(),
-                    }
-                    .into();
-                    state.memory[i..(i + 2)].copy_from_slice(&bytes);
-                }
-            },
-            Instruction::Unrecognized(_) => todo!(),
-        };
-    }
-}
-
-impl Display for Instruction {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Instruction::Arithmetic { op, target, source } => {
-                let var_name = {
-                    let op = Self::MATH[*op as usize];
-                    write!(f, "{op} {target}, {source}")
                 };
-                var_name
+                if taken {
+                    state.jump(offset);
+                }
             }
-            Instruction::ArithmeticImmediate {
+            Instruction::Shift {
                 op,
-                target: register,
-                immediate,
+                width,
+                target,
+                by_cl,
             } => {
-                let op = Self::MATH[*op as usize];
-                write!(f, "{op} {register}, {immediate}")
+                let count = if by_cl {
+                    state.registers.byte(RegisterByte::CL)
+                } else {
+                    1
+                };
+                let value = state.read(target, width);
+                let result = state.registers.shift(op, value, count, width);
+                state.write(target, width, result);
             }
-            Instruction::ArithmeticImmediateToMemory {
-                word_mode,
-                op,
+            Instruction::Test { target, source } => {
+                let width = self.width();
+                let value = state.read(target, width);
+                let operand = state.read(source, width);
+                state
+                    .registers
+                    .arithmetic(ArithOp::And, value, operand, width);
+            }
+            Instruction::TestImmediate {
+                width,
                 target,
                 immediate,
             } => {
-                let op = Self::MATH[*op as usize];
-                let size = if *word_mode { "word" } else { "byte" };
-                write!(f, "{op} {size} {target}, {immediate}")
+                let value = state.read(target, width);
+                state
+                    .registers
+                    .arithmetic(ArithOp::And, value, immediate as u16, width);
+            }
+            Instruction::Unary { op, width, target } => {
+                let value = state.read(target, width);
+                let result = match op {
+                    UnaryOp::Not => !value,
+                    UnaryOp::Neg => state.registers.arithmetic(ArithOp::Sub, 0, value, width),
+                    UnaryOp::Inc | UnaryOp::Dec => {
+                        // the only arithmetic leaving the carry flag alone
+                        let carry = state.registers.flag_carry;
+                        let op = if op == UnaryOp::Inc {
+                            ArithOp::Add
+                        } else {
+                            ArithOp::Sub
+                        };
+                        let result = state.registers.arithmetic(op, value, 1, width);
+                        state.registers.flag_carry = carry;
+                        result
+                    }
+                    // not simulated, so `State::step` stops before getting here
+                    UnaryOp::Mul | UnaryOp::Imul | UnaryOp::Div | UnaryOp::Idiv => unreachable!(),
+                };
+                state.write(target, width, result);
             }
-            Instruction::Jump { marker, offset } => {
-                let negated = if (marker & 1) > 0 { "n" } else { "" };
-                let kind = Self::JUMP[((marker >> 1) & 0b111) as usize];
-                write!(f, "j{negated}{kind} $+2{offset:+}")
+            Instruction::In { width, port } => {
+                let port = port.resolve(&state.registers);
+                let value = state.ports.read(port, width);
+                state.write(Place::register(width, 0), width, value);
             }
-            Instruction::Loop { marker, offset } => {
-                let kind = Self::LOOP[(marker & 0b11) as usize];
-                write!(f, "{kind} $+2{offset:+}")
+            Instruction::Out { width, port } => {
+                let port = port.resolve(&state.registers);
+                let value = state.read(Place::register(width, 0), width);
+                state.ports.write(port, width, value);
             }
-            Instruction::Mov { target, source } => write!(f, "mov {target}, {source}"),
-            Instruction::MovImmediate { target, immediate } => {
-                write!(f, "mov {target}, {immediate}")
+            Instruction::Mov { target, source } => {
+                let width = self.width();
+                let value = state.read(source, width);
+                state.write(target, width, value);
             }
-            Instruction::MovImmediateToMemory {
-                word_mode,
+            Instruction::MovImmediate {
+                width,
                 target,
                 immediate,
-            } => {
-                let size = if *word_mode { "word" } else { "byte" };
-                write!(f, "mov {target}, {size} {immediate}")
+            } => state.write(target, width, immediate as u16),
+            Instruction::Xchg { target, source } => {
+                let width = self.width();
+                let value = state.read(target, width);
+                let other = state.read(source, width);
+                state.write(target, width, other);
+                state.write(source, width, value);
+            }
+            Instruction::Load { op, target, source } => {
+                let Place::Adress(address) = source else {
+                    unreachable!("the decoder only loads from memory");
+                };
+                let value = match op {
+                    LoadOp::Lea => state.effective_offset(address),
+                    LoadOp::Lds | LoadOp::Les => {
+                        let (segment, offset) = read_far_pointer(state, address);
+                        let register = if op == LoadOp::Lds {
+                            SegmentRegister::DS
+                        } else {
+                            SegmentRegister::ES
+                        };
+                        state.registers[register] = segment;
+                        offset
+                    }
+                };
+                state.write(target, Width::Word, value);
+            }
+            Instruction::Implied(op) => {
+                let registers = &mut state.registers;
+                match op {
+                    ImpliedOp::Xlat => {
+                        // [BX + AL], with AL unsigned unlike a displacement
+                        let table = EffectiveAdress {
+                            index: 0b111,
+                            mode: Mode::EffectiveAdressWord,
+                            displacement: registers.byte(RegisterByte::AL).into(),
+                            segment: None,
+                        };
+                        let value = state.read(Place::Adress(table), Width::Byte);
+                        state.registers.set_byte(RegisterByte::AL, value as u8);
+                    }
+                    ImpliedOp::Lahf => {
+                        let status = registers.status_byte();
+                        registers.set_byte(RegisterByte::AH, status);
+                    }
+                    ImpliedOp::Sahf => {
+                        let status = registers.byte(RegisterByte::AH);
+                        registers.set_status_byte(status);
+                    }
+                    ImpliedOp::Cbw => {
+                        registers[RegisterWord::AX] = registers.byte(RegisterByte::AL) as i8 as u16;
+                    }
+                    ImpliedOp::Cwd => {
+                        let negative = registers[RegisterWord::AX] & Width::Word.sign_bit() > 0;
+                        registers[RegisterWord::DX] = if negative { 0xFFFF } else { 0 };
+                    }
+                    ImpliedOp::Iret => {
+                        let offset = state.pop();
+                        let segment = state.pop();
+                        let flags = state.pop();
+                        state.registers.set_flags_word(flags);
+                        state.jump_far(segment, offset);
+                    }
+                    _ => unreachable!("`{self}` is not simulated"),
+                }
+            }
+            Instruction::Call(branch) | Instruction::Jmp(branch) => {
+                let (segment, offset) = match branch {
+                    Branch::Far { segment, offset } => (segment, offset),
+                    Branch::FarIndirect(Place::Adress(address)) => read_far_pointer(state, address),
+                    _ => unreachable!("`{self}` is not simulated"),
+                };
+                if let Instruction::Call(_) = self {
+                    // the return address, which `retf` pops in the opposite order
+                    state.push(state.registers[SegmentRegister::CS]);
+                    state.push(state.ip());
+                }
+                state.jump_far(segment, offset);
             }
-            Instruction::Unrecognized(byte) => {
-                write!(f, "\x1B[1m\x1B[31m{byte:2x}\x1B[0m unrecognized")
+            Instruction::Return { far: true, pop } => {
+                let offset = state.pop();
+                let segment = state.pop();
+                let stack_pointer = &mut state.registers[RegisterWord::SP];
+                *stack_pointer = stack_pointer.wrapping_add(pop.unwrap_or(0));
+                state.jump_far(segment, offset);
             }
+            // stops the simulation in `State::step` before ever getting here
+            _ => unreachable!("`{self}` is not simulated"),
+        };
+    }
+}
+
+/// Reads the far pointer stored at `address`, the offset followed by the segment.
+fn read_far_pointer(state: &mut State, address: EffectiveAdress) -> (u16, u16) {
+    let offset = state.read(Place::Adress(address), Width::Word);
+    let segment_address = EffectiveAdress {
+        displacement: address.displacement.wrapping_add(2),
+        ..address
+    };
+    let segment = state.read(Place::Adress(segment_address), Width::Word);
+    (segment, offset)
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Style::default().write_instruction(f, self)
+    }
+}
+
+/// I/O port addressed by `in` and `out`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    /// One of the first 256 ports, encoded in the instruction itself.
+    Immediate(u8),
+    /// The port held in DX.
+    Dx,
+}
+
+impl Port {
+    fn resolve(self, registers: &Registers) -> u16 {
+        match self {
+            Port::Immediate(port) => port.into(),
+            Port::Dx => registers[RegisterWord::DX],
+        }
+    }
+}
+
+/// Operand width of an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Word,
+}
+
+impl Width {
+    fn from_bit(word_mode: bool) -> Self {
+        if word_mode {
+            Self::Word
+        } else {
+            Self::Byte
+        }
+    }
+
+    pub fn mask(self) -> u16 {
+        match self {
+            Width::Byte => 0xFF,
+            Width::Word => 0xFFFF,
+        }
+    }
+
+    pub fn sign_bit(self) -> u16 {
+        match self {
+            Width::Byte => 0x80,
+            Width::Word => 0x8000,
+        }
+    }
+}
+
+impl Display for Width {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Width::Byte => f.write_str("byte"),
+            Width::Word => f.write_str("word"),
         }
     }
 }
 
+/// Two operand arithmetic and logic operations, in the order of their `reg` field encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ArithOp {
+    Add,
+    Or,
+    Adc,
+    Sbb,
+    And,
+    Sub,
+    Xor,
+    Cmp,
+}
+
+impl ArithOp {
+    fn from_octal(octal: u8) -> Option<Self> {
+        if octal > 0b111 {
+            return None;
+        }
+        Some(unsafe { std::mem::transmute::<u8, Self>(octal) })
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        const MNEMONICS: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+        MNEMONICS[self as usize]
+    }
+}
+
+/// Shift and rotate operations, in the order of their `reg` field encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ShiftOp {
+    Rol,
+    Ror,
+    Rcl,
+    Rcr,
+    Shl,
+    Shr,
+    /// Undocumented encoding that behaves like [`ShiftOp::Shl`].
+    Sal,
+    Sar,
+}
+
+impl ShiftOp {
+    fn from_octal(octal: u8) -> Option<Self> {
+        if octal > 0b111 {
+            return None;
+        }
+        Some(unsafe { std::mem::transmute::<u8, Self>(octal) })
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        const MNEMONICS: [&str; 8] = ["rol", "ror", "rcl", "rcr", "shl", "shr", "sal", "sar"];
+        MNEMONICS[self as usize]
+    }
+
+    pub fn is_rotate(self) -> bool {
+        matches!(
+            self,
+            ShiftOp::Rol | ShiftOp::Ror | ShiftOp::Rcl | ShiftOp::Rcr
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+    Mul,
+    Imul,
+    Div,
+    Idiv,
+    Inc,
+    Dec,
+}
+
+impl UnaryOp {
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            UnaryOp::Not => "not",
+            UnaryOp::Neg => "neg",
+            UnaryOp::Mul => "mul",
+            UnaryOp::Imul => "imul",
+            UnaryOp::Div => "div",
+            UnaryOp::Idiv => "idiv",
+            UnaryOp::Inc => "inc",
+            UnaryOp::Dec => "dec",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadOp {
+    Lea,
+    Lds,
+    Les,
+}
+
+impl LoadOp {
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            LoadOp::Lea => "lea",
+            LoadOp::Lds => "lds",
+            LoadOp::Les => "les",
+        }
+    }
+}
+
+/// String instructions, numbered by bits 1 to 3 of their opcodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum StringOp {
+    Movs = 2,
+    Cmps = 3,
+    Stos = 5,
+    Lods = 6,
+    Scas = 7,
+}
+
+impl StringOp {
+    fn from_opcode(opcode: u8) -> Option<Self> {
+        match (opcode >> 1) & 0b111 {
+            2 => Some(StringOp::Movs),
+            3 => Some(StringOp::Cmps),
+            5 => Some(StringOp::Stos),
+            6 => Some(StringOp::Lods),
+            7 => Some(StringOp::Scas),
+            _ => None,
+        }
+    }
+
+    pub fn mnemonic(self, width: Width) -> &'static str {
+        match (self, width) {
+            (StringOp::Movs, Width::Byte) => "movsb",
+            (StringOp::Movs, Width::Word) => "movsw",
+            (StringOp::Cmps, Width::Byte) => "cmpsb",
+            (StringOp::Cmps, Width::Word) => "cmpsw",
+            (StringOp::Stos, Width::Byte) => "stosb",
+            (StringOp::Stos, Width::Word) => "stosw",
+            (StringOp::Lods, Width::Byte) => "lodsb",
+            (StringOp::Lods, Width::Word) => "lodsw",
+            (StringOp::Scas, Width::Byte) => "scasb",
+            (StringOp::Scas, Width::Word) => "scasw",
+        }
+    }
+}
+
+/// Repeat prefix of a string instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    /// `0xF3`, repeating while CX is not zero, and for `cmps` and `scas` while ZF is set.
+    Rep,
+    /// `0xF2`, repeating while CX is not zero, and for `cmps` and `scas` while ZF is clear.
+    Repne,
+}
+
+impl Repeat {
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Repeat::Rep => "rep",
+            Repeat::Repne => "repne",
+        }
+    }
+}
+
+/// Where a `call` or unconditional `jmp` transfers control to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Branch {
+    /// Relative to the end of a two byte `jmp`.
+    Short(i8),
+    /// Relative to the end of a three byte `call` or `jmp`.
+    Near(i16),
+    /// Near address held in a register or memory.
+    Indirect(Place),
+    Far {
+        segment: u16,
+        offset: u16,
+    },
+    /// Far pointer stored in memory, offset first.
+    FarIndirect(Place),
+}
+
+/// Instructions without any explicit operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImpliedOp {
+    Daa,
+    Das,
+    Aaa,
+    Aas,
+    Cbw,
+    Cwd,
+    Wait,
+    Pushf,
+    Popf,
+    Sahf,
+    Lahf,
+    Int3,
+    Into,
+    Iret,
+    Aam,
+    Aad,
+    Xlat,
+    /// Bus lock prefix, shown on its own since it applies to whatever instruction follows.
+    Lock,
+    Hlt,
+    Cmc,
+    Clc,
+    Stc,
+    Cli,
+    Sti,
+    Cld,
+    Std,
+}
+
+impl ImpliedOp {
+    /// The instruction with the one byte `opcode`, if there is one.
+    fn from_opcode(opcode: u8) -> Option<Self> {
+        use ImpliedOp::*;
+        Some(match opcode {
+            0x27 => Daa,
+            0x2F => Das,
+            0x37 => Aaa,
+            0x3F => Aas,
+            0x98 => Cbw,
+            0x99 => Cwd,
+            0x9B => Wait,
+            0x9C => Pushf,
+            0x9D => Popf,
+            0x9E => Sahf,
+            0x9F => Lahf,
+            0xCC => Int3,
+            0xCE => Into,
+            0xCF => Iret,
+            0xD7 => Xlat,
+            0xF0 => Lock,
+            0xF4 => Hlt,
+            0xF5 => Cmc,
+            0xF8 => Clc,
+            0xF9 => Stc,
+            0xFA => Cli,
+            0xFB => Sti,
+            0xFC => Cld,
+            0xFD => Std,
+            _ => return None,
+        })
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        use ImpliedOp::*;
+        match self {
+            Daa => "daa",
+            Das => "das",
+            Aaa => "aaa",
+            Aas => "aas",
+            Cbw => "cbw",
+            Cwd => "cwd",
+            Wait => "wait",
+            Pushf => "pushf",
+            Popf => "popf",
+            Sahf => "sahf",
+            Lahf => "lahf",
+            Int3 => "int3",
+            Into => "into",
+            Iret => "iret",
+            Aam => "aam",
+            Aad => "aad",
+            Xlat => "xlat",
+            Lock => "lock",
+            Hlt => "hlt",
+            Cmc => "cmc",
+            Clc => "clc",
+            Stc => "stc",
+            Cli => "cli",
+            Sti => "sti",
+            Cld => "cld",
+            Std => "std",
+        }
+    }
+}
+
+/// Conditions of the short conditional jumps, in the order of their opcodes `0x70..=0x7F`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum JumpCondition {
+    Overflow,
+    NotOverflow,
+    Below,
+    NotBelow,
+    Zero,
+    NotZero,
+    BelowOrEqual,
+    NotBelowOrEqual,
+    Sign,
+    NotSign,
+    Parity,
+    NotParity,
+    Less,
+    NotLess,
+    LessOrEqual,
+    NotLessOrEqual,
+}
+
+impl JumpCondition {
+    fn from_opcode(opcode: u8) -> Self {
+        unsafe { std::mem::transmute::<u8, Self>(opcode & 0x0F) }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        const MNEMONICS: [&str; 16] = [
+            "jo", "jno", "jb", "jnb", "jz", "jnz", "jbe", "jnbe", "js", "jns", "jp", "jnp", "jl",
+            "jnl", "jle", "jnle",
+        ];
+        MNEMONICS[self as usize]
+    }
+
+    /// Whether the jump is taken given the current state of the flags.
+    pub fn holds(self, registers: &Registers) -> bool {
+        use JumpCondition::*;
+        let flag = match self {
+            Overflow | NotOverflow => registers.flag_overflow,
+            Below | NotBelow => registers.flag_carry,
+            Zero | NotZero => registers.flag_zero,
+            BelowOrEqual | NotBelowOrEqual => registers.flag_carry || registers.flag_zero,
+            Sign | NotSign => registers.flag_sign,
+            Parity | NotParity => registers.flag_parity,
+            Less | NotLess => registers.flag_sign != registers.flag_overflow,
+            LessOrEqual | NotLessOrEqual => {
+                registers.flag_zero || registers.flag_sign != registers.flag_overflow
+            }
+        };
+        // every odd condition is the negation of the one before it
+        flag ^ (self as u8 & 1 > 0)
+    }
+}
+
+/// Loop instructions, in the order of their opcodes `0xE0..=0xE3`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LoopKind {
+    Loopnz,
+    Loopz,
+    Loop,
+    Jcxz,
+}
+
+impl LoopKind {
+    fn from_opcode(opcode: u8) -> Self {
+        unsafe { std::mem::transmute::<u8, Self>(opcode & 0b11) }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        const MNEMONICS: [&str; 4] = ["loopnz", "loopz", "loop", "jcxz"];
+        MNEMONICS[self as usize]
+    }
+}
+
+macro_rules! display_mnemonic {
+    ($($kind:ty),*) => {$(
+        impl Display for $kind {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.mnemonic())
+            }
+        }
+    )*};
+}
+display_mnemonic!(
+    ArithOp,
+    ShiftOp,
+    UnaryOp,
+    LoadOp,
+    Repeat,
+    ImpliedOp,
+    JumpCondition,
+    LoopKind
+);
+
+/// A memory operand, addressed through registers and a displacement or directly. The fields keep
+/// the encoding, the accessors expose what it means.
 #[derive(Debug, Clone, Copy)]
 pub struct EffectiveAdress {
     index: u8,
     mode: Mode,
-    displacement: u16,
+    /// Sign extended displacement, or the address itself for direct addressing.
+    displacement: i16,
+    /// Segment override prefix in front of the instruction.
+    segment: Option<SegmentRegister>,
 }
 impl EffectiveAdress {
-    const ADRESS_CALCULATION: [&'static str; 8] = [
+    pub(crate) const ADRESS_CALCULATION: [&'static str; 8] = [
         "BX + SI", "BX + DI", "BP + SI", "BP + DI", "SI", "DI", "BP", "BX",
     ];
+
+    /// Whether the address is given directly by the instruction, without any registers.
+    pub fn is_direct(&self) -> bool {
+        self.mode == Mode::EffectiveAdress && self.index == 0b110
+    }
+
+    /// The registers adding up to the address, `None` for a direct address.
+    pub fn base(&self) -> Option<AddressBase> {
+        (!self.is_direct()).then(|| AddressBase::from_octal(self.index))
+    }
+
+    /// The displacement added to the registers, or the address itself for a direct address.
+    pub fn displacement(&self) -> i16 {
+        self.displacement
+    }
+
+    /// How wide the displacement is encoded, `None` if the instruction leaves it out.
+    pub fn displacement_width(&self) -> Option<Width> {
+        match self.mode {
+            Mode::EffectiveAdressByte => Some(Width::Byte),
+            Mode::EffectiveAdressWord => Some(Width::Word),
+            _ if self.is_direct() => Some(Width::Word),
+            _ => None,
+        }
+    }
+
+    /// The segment override prefix in front of the instruction, if any.
+    pub fn segment(&self) -> Option<SegmentRegister> {
+        self.segment
+    }
+}
+
+/// The registers a memory operand is addressed through, in the order of their encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AddressBase {
+    BxSi,
+    BxDi,
+    BpSi,
+    BpDi,
+    Si,
+    Di,
+    Bp,
+    Bx,
+}
+
+impl AddressBase {
+    fn from_octal(octal: u8) -> Self {
+        use AddressBase::*;
+        [BxSi, BxDi, BpSi, BpDi, Si, Di, Bp, Bx][usize::from(octal & 0b111)]
+    }
+
+    /// The base or index register, and the index register added to the base if there is one.
+    pub fn registers(self) -> (RegisterWord, Option<RegisterWord>) {
+        use RegisterWord::*;
+        match self {
+            AddressBase::BxSi => (BX, Some(SI)),
+            AddressBase::BxDi => (BX, Some(DI)),
+            AddressBase::BpSi => (BP, Some(SI)),
+            AddressBase::BpDi => (BP, Some(DI)),
+            AddressBase::Si => (SI, None),
+            AddressBase::Di => (DI, None),
+            AddressBase::Bp => (BP, None),
+            AddressBase::Bx => (BX, None),
+        }
+    }
+}
+/// Memory operands are equal when they address the same memory, whatever width their displacement
+/// is encoded in.
+impl PartialEq for EffectiveAdress {
+    fn eq(&self, other: &Self) -> bool {
+        self.base() == other.base()
+            && self.displacement == other.displacement
+            && self.segment == other.segment
+    }
 }
+impl Eq for EffectiveAdress {}
+
 impl std::fmt::Display for EffectiveAdress {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.mode == Mode::EffectiveAdress && self.index == 0b110 {
-            write!(f, "[{}]", self.displacement)
-        } else {
-            write!(
-                f,
-                "[{} + {}]",
-                Self::ADRESS_CALCULATION[self.index as usize],
-                self.displacement
-            )
-        }
+        Style::default().write_address(f, self)
     }
 }
 
@@ -379,48 +1044,58 @@ impl Mode {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Place {
     Byte(RegisterByte),
     Word(RegisterWord),
+    Segment(SegmentRegister),
     Adress(EffectiveAdress),
 }
 
 impl std::fmt::Display for Place {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Place::Byte(reg) => write!(f, "{reg:?}"),
-            Place::Word(reg) => write!(f, "{reg:?}"),
-            Place::Adress(ea) => ea.fmt(f),
-        }
+        Style::default().write_place(f, self)
     }
 }
 
 impl Place {
-    fn register(word_mode: bool, disc: u8) -> Self {
-        if word_mode {
-            Self::Word(RegisterWord::from_octal(disc).unwrap())
-        } else {
-            Self::Byte(RegisterByte::from_octal(disc).unwrap())
+    pub(crate) fn register(width: Width, disc: u8) -> Self {
+        match width {
+            Width::Word => Self::Word(RegisterWord::from_octal(disc).unwrap()),
+            Width::Byte => Self::Byte(RegisterByte::from_octal(disc).unwrap()),
         }
     }
 
-    fn address(r_m: u8, mode: Mode, memory: &mut &[u8]) -> Self {
-        let displacement = if mode == Mode::EffectiveAdress && r_m == 0b110 {
-            advance_by(memory, 2)
-        } else {
-            advance_by(memory, mode as usize)
-        } as u16;
-        Self::Adress(EffectiveAdress {
+    fn address(r_m: u8, mode: Mode, memory: &mut &[u8]) -> Option<Self> {
+        let displacement = match mode {
+            Mode::EffectiveAdress if r_m == 0b110 => advance_by(memory, 2)? as u16 as i16,
+            Mode::EffectiveAdressByte => advance_by(memory, 1)? as u8 as i8 as i16,
+            Mode::EffectiveAdressWord => advance_by(memory, 2)? as u16 as i16,
+            _ => 0,
+        };
+        Some(Self::Adress(EffectiveAdress {
             index: r_m,
             mode,
             displacement,
-        })
+            segment: None,
+        }))
     }
 
-    fn resolve_rm(r_m: u8, mode: Mode, word_mode: bool, memory: &mut &[u8]) -> Self {
+    /// A memory operand at the 16 bit address encoded directly in the instruction.
+    fn direct(memory: &mut &[u8]) -> Option<Self> {
+        Self::address(0b110, Mode::EffectiveAdress, memory)
+    }
+
+    fn address_mut(&mut self) -> Option<&mut EffectiveAdress> {
+        match self {
+            Place::Adress(address) => Some(address),
+            _ => None,
+        }
+    }
+
+    fn resolve_rm(r_m: u8, mode: Mode, width: Width, memory: &mut &[u8]) -> Option<Self> {
         if let Mode::RegisterToRegister = mode {
-            Place::register(word_mode, r_m)
+            Some(Place::register(width, r_m))
         } else {
             Place::address(r_m, mode, memory)
         }
@@ -428,7 +1103,7 @@ impl Place {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[rustfmt::skip]
 #[repr(u8)]
 pub enum RegisterByte {
@@ -446,7 +1121,7 @@ impl RegisterByte {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[rustfmt::skip]
 #[repr(u8)]
 pub enum RegisterWord {
@@ -462,3 +1137,21 @@ impl RegisterWord {
         Some(unsafe { std::mem::transmute::<u8, Self>(octal) })
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SegmentRegister {
+    ES,
+    CS,
+    SS,
+    DS,
+}
+
+impl SegmentRegister {
+    fn from_octal(octal: u8) -> Option<Self> {
+        if octal > 0b11 {
+            return None;
+        }
+        Some(unsafe { std::mem::transmute::<u8, Self>(octal) })
+    }
+}
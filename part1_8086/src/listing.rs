@@ -0,0 +1,158 @@
+//! Mapping of addresses back to the source lines of NASM listings, as written by `nasm -l`.
+//!
+//! Every line of a listing starts with the source line number, right aligned in 6 columns. Lines
+//! emitting bytes follow it with their address as 8 hexadecimal digits and the bytes themselves,
+//! then comes the nesting level of macro expansions if any and finally the source text:
+//!
+//! ```text
+//!      3                                  start:
+//!      4 00000000 B90300                      mov cx, 3 ; counter
+//!      5 00000003 E2FE                    .again: loop .again
+//! ```
+//!
+//! Data too long for a single line carries on over lines with the same number and no source.
+
+use std::{collections::BTreeMap, fmt::Display};
+
+/// A line of source emitting an instruction or data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    /// Line number within the assembled file, counting from 1.
+    pub number: usize,
+    /// The source of the line, without its label and comment.
+    pub text: String,
+}
+
+/// Where an address lies in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location<'a> {
+    pub line: &'a SourceLine,
+    /// The closest label at or before the address and how far past it the address is.
+    pub label: Option<(&'a str, usize)>,
+}
+
+impl Display for Location<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}", self.line.number)?;
+        match self.label {
+            Some((label, 0)) => write!(f, ", {label}"),
+            Some((label, offset)) => write!(f, ", {label}+{offset}"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    lines: BTreeMap<usize, SourceLine>,
+    labels: BTreeMap<usize, String>,
+}
+
+impl SourceMap {
+    pub fn parse(listing: &str) -> Result<Self, String> {
+        let mut map = Self::default();
+        // labels on lines of their own belong to whatever comes next
+        let mut pending_labels = Vec::new();
+        let mut scope = String::new();
+        for (index, line) in listing.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let error = |what: &str| format!("listing line {}: {what}", index + 1);
+            let number = line
+                .get(..6)
+                .and_then(|number| number.trim().parse().ok())
+                .ok_or_else(|| error("expected a line number"))?;
+            let rest = line.get(7..).unwrap_or_default();
+            let (address, rest) = match rest.get(..8) {
+                Some(digits) if digits.bytes().all(|digit| digit.is_ascii_hexdigit()) => {
+                    let address = usize::from_str_radix(digits, 16)
+                        .map_err(|_| error("address out of range"))?;
+                    (Some(address), skip_data(&rest[8..]))
+                }
+                _ => (None, rest),
+            };
+            let rest = rest.trim_start();
+            let rest = match rest.strip_prefix('<') {
+                Some(level) => level.split_once('>').map_or(rest, |(_, rest)| rest),
+                None => rest,
+            };
+
+            let (label, text) = split_label(strip_comment(rest));
+            if let Some(label) = label {
+                let label = match label.strip_prefix('.') {
+                    Some(_) => format!("{scope}{label}"),
+                    None => {
+                        scope = label.to_owned();
+                        label.to_owned()
+                    }
+                };
+                pending_labels.push(label);
+            }
+            let Some(address) = address else {
+                continue;
+            };
+            for label in pending_labels.drain(..) {
+                map.labels.entry(address).or_insert(label);
+            }
+            if !text.is_empty() {
+                map.lines.entry(address).or_insert(SourceLine {
+                    number,
+                    text: text.to_owned(),
+                });
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// The source line of the instruction starting at `address`, if any starts there.
+    pub fn locate(&self, address: usize) -> Option<Location<'_>> {
+        let line = self.lines.get(&address)?;
+        let label = self
+            .labels
+            .range(..=address)
+            .next_back()
+            .map(|(start, label)| (label.as_str(), address - start));
+        Some(Location { line, label })
+    }
+}
+
+/// Skips the bytes following the address, which are written as hexadecimal digits with relocated
+/// parts in brackets or parentheses, or as `<res n>` for reserved space.
+fn skip_data(rest: &str) -> &str {
+    let rest = rest.trim_start();
+    if rest.starts_with("<res") {
+        return rest.split_once('>').map_or("", |(_, rest)| rest);
+    }
+    rest.split_once(char::is_whitespace)
+        .map_or("", |(_, rest)| rest)
+}
+
+/// Cuts off a `;` comment, unless the semicolon is quoted.
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (index, c) in text.char_indices() {
+        match (c, quote) {
+            ('\'' | '"' | '`', None) => quote = Some(c),
+            (_, Some(open)) if c == open => quote = None,
+            (';', None) => return text[..index].trim(),
+            _ => (),
+        }
+    }
+    text.trim()
+}
+
+/// Splits off a leading `label:` from the source text of a line.
+fn split_label(text: &str) -> (Option<&str>, &str) {
+    let end = text
+        .find(|c: char| !(c.is_alphanumeric() || "_.$@?#~".contains(c)))
+        .unwrap_or(text.len());
+    match text[end..].strip_prefix(':') {
+        Some(rest) if end > 0 => (Some(&text[..end]), rest.trim()),
+        _ => (None, text),
+    }
+}
@@ -1,56 +1,273 @@
-use std::{env::args, fs::File, io::Write};
+use std::{
+    env::args,
+    io::{stdin, stdout, BufRead, Write},
+    path::Path,
+    process::ExitCode,
+};
 
+use cli::{Command, Options};
 use sim86::{
+    cfg::ControlFlowGraph,
     decode,
     exec::{self, State},
-    read_listing,
+    format::Style,
+    listing::SourceMap,
+    ports::{Console, Pit},
+    prefetch::BusUnit,
+    setup::Setup,
+    stats::{InstructionMix, Statistics},
+    tui::{Viewer, CLEAR, MEMORY_ROWS},
 };
 
-fn main() {
-    let args = &mut args();
-    let _ = args.next();
-    let mut dump = false;
+mod cli;
+
+fn main() -> ExitCode {
+    let options = match Options::parse(args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}", cli::HELP);
+            return ExitCode::SUCCESS;
+        }
+        Err(error) => {
+            eprintln!("error: {error}\n\n{}", cli::HELP);
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut failed = false;
+    for (index, path) in options.files.iter().enumerate() {
+        if let Err(error) = process_file(&options, path, index) {
+            eprintln!("{path}: {error}");
+            failed = true;
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn process_file(options: &Options, path: &str, index: usize) -> Result<(), String> {
+    let program = std::fs::read(path).map_err(|error| format!("failed to read: {error}"))?;
+    if options.files.len() > 1 {
+        println!("; {path}");
+    }
+
+    match options.command {
+        Command::Disasm => {
+            let disassembly = decode::all_instructions_with(&program, &options.style);
+            println!("{disassembly}");
+            return Ok(());
+        }
+        Command::Cfg => {
+            let graph = ControlFlowGraph::from_code(&program);
+            print!("{}", graph.to_dot());
+            return Ok(());
+        }
+        Command::Stats => {
+            print!("{}", InstructionMix::from_code(&program).report());
+            return Ok(());
+        }
+        Command::Exec | Command::Debug | Command::Tui => (),
+    }
 
     let mut state = State::default();
-    while let Some(arg) = args.next() {
-        match arg.as_str() {
-            "-dump" => {
-                dump = true;
-                continue;
-            }
-            "-exec" => {
-                let Some(path_bin) = args.next() else {
-                    eprintln!("no binary provided");
-                    return;
-                };
+    state
+        .load_program(&program)
+        .map_err(|error| format!("failed to load: {error}"))?;
+    if let Some(init_path) = options.init_path(path, index) {
+        let text = std::fs::read_to_string(&init_path)
+            .map_err(|error| format!("failed to read setup {}: {error}", init_path.display()))?;
+        let setup =
+            Setup::parse(&text).map_err(|error| format!("{}: {error}", init_path.display()))?;
+        let base = init_path.parent().unwrap_or(Path::new(""));
+        setup
+            .apply(&mut state, base)
+            .map_err(|error| format!("{}: {error}", init_path.display()))?;
+    }
+    if options.stats {
+        state.statistics = Some(Statistics::new(options.cycles));
+    }
+    if let Some(cpu) = options.timing {
+        state.bus = Some(BusUnit::new(
+            cpu,
+            options.wait_states,
+            state.instruction_pointer,
+        ));
+    }
+    if options.devices {
+        state.attach_device(Console::PORT..=Console::PORT, Console::stdio());
+        state.attach_device(Pit::PORTS, Pit::default());
+    }
+    state.warn_code_writes = options.warn_code_writes;
+    if let Some(listing_path) = options.listing_path(path, index) {
+        let listing = std::fs::read_to_string(&listing_path).map_err(|error| {
+            format!("failed to read listing {}: {error}", listing_path.display())
+        })?;
+        let source = SourceMap::parse(&listing)
+            .map_err(|error| format!("{}: {error}", listing_path.display()))?;
+        if source.is_empty() {
+            return Err(format!(
+                "listing {} has no instructions",
+                listing_path.display()
+            ));
+        }
+        state.source = Some(source);
+    }
+    for watchpoint in &options.watchpoints {
+        state.add_watchpoint(watchpoint.clone());
+    }
+
+    let result = match options.command {
+        Command::Debug => debug(&mut state),
+        Command::Tui => tui(&mut state, options.style),
+        _ if options.quiet => exec::all_instructions(&mut state),
+        _ => exec::all_instructions_and_print(&mut state),
+    };
+    state.registers.print();
+    if !state.watchpoints.log.is_empty() {
+        println!("\nWatched accesses:");
+        for access in &state.watchpoints.log {
+            println!("  {access}");
+        }
+    }
+    if options.warn_code_writes && !state.code_writes.is_empty() {
+        println!("\nWrites into code:");
+        for write in &state.code_writes {
+            println!("  {write}");
+        }
+    }
+    if let Some(statistics) = &state.statistics {
+        print!("\n{}", statistics.report());
+    }
+    if let Some(bus) = &state.bus {
+        print!("\n{}", bus.report());
+    }
 
-                state.load_program(&path_bin);
-                // let (memory, _length) = read_listing(&path_bin);
-                // let mut registers = Registers::default();
-                exec::all_instructions_and_print(&mut state);
+    if options.dump {
+        let dump_path = options.dump_path(path, index);
+        std::fs::write(&dump_path, state.memory).map_err(|error| {
+            format!("failed to dump memory to {}: {error}", dump_path.display())
+        })?;
+    }
+    result.map_err(|stop| format!("simulation stopped: {stop}"))
+}
+
+const DEBUG_HELP: &str = "  s, step (or empty line)   execute the next instruction
+  c, continue               run until the end of the program
+  r, registers              print the registers
+  m, memory <addr> [len]    print len bytes of memory starting at addr (hexadecimal)
+  q, quit                   stop simulating this file
+  h, help                   print this help";
+
+/// Steps through the program following commands read from stdin.
+fn debug(state: &mut State) -> Result<(), exec::StopReason> {
+    println!("{DEBUG_HELP}");
+    let mut lines = stdin().lock().lines();
+    loop {
+        // breaking watchpoints hand control back to the user, who may carry on
+        if let Some(stop @ exec::StopReason::Watchpoint(_)) = state.stop {
+            println!("; {stop}");
+            state.stop = None;
+        }
+        if !state.is_running() {
+            break;
+        }
+        let (_, instruction) = state.next_instruction();
+        let address = state.instruction_pointer;
+        match state
+            .source
+            .as_ref()
+            .and_then(|source| source.locate(address))
+        {
+            Some(location) => println!("; next: {} ({location})", location.line.text),
+            None => println!("; next: {instruction}"),
+        }
+        let Some(Ok(line)) = lines.next() else {
+            break;
+        };
+        let mut words = line.split_whitespace();
+        match words.next() {
+            None | Some("s" | "step") => exec::step_and_print(state),
+            Some("c" | "continue") => {
+                exec::all_instructions_and_print(state).ok();
+            }
+            Some("r" | "registers") => {
                 state.registers.print();
+                println!(
+                    "IP: {}\nflags: {}",
+                    state.ip(),
+                    state.registers.flags_string()
+                );
             }
-            _ => {
-                let path_bin = arg;
-                let (memory, length) = read_listing(&path_bin);
-                let disassembly = decode::all_instructions(&memory[..length]);
-                println!("{disassembly}");
+            Some("m" | "memory") => {
+                let mut number = |default| {
+                    words
+                        .next()
+                        .map_or(Some(default), |word| usize::from_str_radix(word, 16).ok())
+                };
+                match (number(0), number(16)) {
+                    (Some(start), Some(length)) => {
+                        let end = start.saturating_add(length).min(state.memory.len());
+                        let start = start.min(end);
+                        for (row, bytes) in state.memory[start..end].chunks(16).enumerate() {
+                            println!("{:04x}: {bytes:02x?}", start + 16 * row);
+                        }
+                    }
+                    _ => println!("expected hexadecimal numbers"),
+                }
             }
+            Some("q" | "quit") => break,
+            Some("h" | "help") => println!("{DEBUG_HELP}"),
+            Some(command) => println!("unknown command `{command}`, try `help`"),
         }
     }
+    state.stop.map_or(Ok(()), Err)
+}
 
-    if dump {
-        let mut count = 0;
-        loop {
-            if let Ok(mut file) = File::create_new(format!("sim8086_memory_{count}.data")) {
-                match file.write_all(&state.memory) {
-                    Ok(_) => (),
-                    Err(error) => eprintln!("failed to dump memory to file with error: {error}"),
+const TUI_HELP: &str =
+    "s, step [n]: execute n instructions (or empty line)   c, continue: run to the end
+m, memory <addr>: show memory at addr (hexadecimal)   j/k: scroll memory   q, quit";
+
+/// Like [`debug`], but redraws the whole terminal with the state of the simulation after every
+/// command.
+fn tui(state: &mut State, style: Style) -> Result<(), exec::StopReason> {
+    let mut viewer = Viewer::new(state, style);
+    let mut lines = stdin().lock().lines();
+    let mut message = String::new();
+    loop {
+        print!("{CLEAR}{}{TUI_HELP}\n{message}\n> ", viewer.render(state));
+        stdout().flush().ok();
+        // breaking watchpoints show up in the frame, after which the user may carry on
+        if let Some(exec::StopReason::Watchpoint(_)) = state.stop {
+            state.stop = None;
+        }
+        let Some(Ok(line)) = lines.next() else {
+            break;
+        };
+        message.clear();
+        let mut words = line.split_whitespace();
+        match words.next() {
+            None | Some("s" | "step") => match words.next().map(str::parse) {
+                None => viewer.advance(state, Some(1)),
+                Some(Ok(steps)) => viewer.advance(state, Some(steps)),
+                Some(Err(_)) => message = "expected a number of instructions".to_owned(),
+            },
+            Some("c" | "continue") => viewer.advance(state, None),
+            Some("m" | "memory") => {
+                match words.next().map(|word| usize::from_str_radix(word, 16)) {
+                    Some(Ok(address)) => viewer.show_memory(address),
+                    _ => message = "expected a hexadecimal address".to_owned(),
                 }
-                break;
-            } else {
-                count += 1;
             }
+            Some("j") => viewer.scroll_memory(MEMORY_ROWS as isize),
+            Some("k") => viewer.scroll_memory(-(MEMORY_ROWS as isize)),
+            Some("q" | "quit") => break,
+            Some(command) => message = format!("unknown command `{command}`"),
         }
     }
+    println!();
+    state.stop.map_or(Ok(()), Err)
 }
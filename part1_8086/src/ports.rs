@@ -0,0 +1,228 @@
+//! Port I/O for the `in` and `out` instructions, routed to pluggable device models.
+
+use std::{
+    io::{Read, Write},
+    ops::RangeInclusive,
+};
+
+use crate::Width;
+
+/// A device on the I/O bus, answering to the ports it was attached to on [`State`].
+///
+/// [`State`]: crate::exec::State
+pub trait PortDevice {
+    /// Handles an `in` from `port`.
+    fn read(&mut self, port: u16, width: Width) -> u16;
+
+    /// Handles an `out` of `value` to `port`.
+    fn write(&mut self, port: u16, width: Width, value: u16);
+
+    /// Lets the device advance its own notion of time after every executed instruction, by the
+    /// estimated number of CPU clocks that instruction took.
+    fn tick(&mut self, _clocks: u32) {}
+}
+
+/// The devices attached to a [`State`], along with the ports each of them answers to.
+///
+/// [`State`]: crate::exec::State
+#[derive(Default)]
+pub struct PortBus {
+    devices: Vec<(RangeInclusive<u16>, Box<dyn PortDevice>)>,
+}
+
+impl PortBus {
+    pub fn attach(&mut self, ports: RangeInclusive<u16>, device: Box<dyn PortDevice>) {
+        self.devices.push((ports, device));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    fn device(&mut self, port: u16) -> Option<&mut Box<dyn PortDevice>> {
+        self.devices
+            .iter_mut()
+            .find(|(ports, _)| ports.contains(&port))
+            .map(|(_, device)| device)
+    }
+
+    /// Reads from `port`. Ports without a device float high, reading all ones.
+    pub fn read(&mut self, port: u16, width: Width) -> u16 {
+        match self.device(port) {
+            Some(device) => device.read(port, width) & width.mask(),
+            None => width.mask(),
+        }
+    }
+
+    /// Writes to `port`. Writes to ports without a device are dropped.
+    pub fn write(&mut self, port: u16, width: Width, value: u16) {
+        if let Some(device) = self.device(port) {
+            device.write(port, width, value & width.mask());
+        }
+    }
+
+    pub fn tick(&mut self, clocks: u32) {
+        for (_, device) in &mut self.devices {
+            device.tick(clocks);
+        }
+    }
+}
+
+/// Character device passing bytes written to it to `output`, and handing out bytes from `input`
+/// when read, or zero once the input runs out.
+pub struct Console<R, W> {
+    pub input: R,
+    pub output: W,
+}
+
+impl Console<std::io::Stdin, std::io::Stdout> {
+    /// Port the console is conventionally attached to, as on the Bochs debug console.
+    pub const PORT: u16 = 0xE9;
+
+    pub fn stdio() -> Self {
+        Self {
+            input: std::io::stdin(),
+            output: std::io::stdout(),
+        }
+    }
+}
+
+impl<R: Read, W: Write> PortDevice for Console<R, W> {
+    fn read(&mut self, _port: u16, _width: Width) -> u16 {
+        let mut byte = [0];
+        match self.input.read(&mut byte) {
+            Ok(1) => byte[0].into(),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, _port: u16, _width: Width, value: u16) {
+        let _ = self.output.write_all(&[value as u8]);
+        let _ = self.output.flush();
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    LowByte,
+    HighByte,
+    #[default]
+    LowThenHigh,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Channel {
+    reload: u16,
+    count: u16,
+    access: Access,
+    latched: Option<u16>,
+    /// Whether the next byte of a low-then-high access is the high one.
+    high_next: bool,
+}
+
+impl Channel {
+    fn next_byte_is_high(&mut self) -> bool {
+        match self.access {
+            Access::LowByte => false,
+            Access::HighByte => true,
+            Access::LowThenHigh => {
+                self.high_next = !self.high_next;
+                !self.high_next
+            }
+        }
+    }
+}
+
+/// Stub of the 8253 programmable interval timer on ports `0x40..=0x43`.
+///
+/// All three channels count down continuously at the PIT input frequency, a quarter of the
+/// 4.77 MHz CPU clock, and reload when they reach zero. The counting modes themselves and the
+/// output pins are not modeled.
+#[derive(Debug, Default, Clone)]
+pub struct Pit {
+    channels: [Channel; 3],
+    /// CPU clocks not yet accounted for by a whole PIT tick.
+    clocks: u32,
+}
+
+impl Pit {
+    pub const PORTS: RangeInclusive<u16> = 0x40..=0x43;
+
+    /// The current count of `channel`, without latching it.
+    pub fn count(&self, channel: usize) -> u16 {
+        self.channels[channel].count
+    }
+}
+
+impl PortDevice for Pit {
+    fn read(&mut self, port: u16, _width: Width) -> u16 {
+        let Some(channel) = self.channels.get_mut((port - Self::PORTS.start()) as usize) else {
+            return 0xFF;
+        };
+        let value = channel.latched.unwrap_or(channel.count);
+        let high = channel.next_byte_is_high();
+        if high || channel.access != Access::LowThenHigh {
+            channel.latched = None;
+        }
+        if high {
+            value >> 8
+        } else {
+            value & 0xFF
+        }
+    }
+
+    fn write(&mut self, port: u16, _width: Width, value: u16) {
+        let value = value as u8;
+        if port == *Self::PORTS.end() {
+            let Some(channel) = self.channels.get_mut((value >> 6) as usize) else {
+                return;
+            };
+            channel.high_next = false;
+            channel.access = match (value >> 4) & 0b11 {
+                0b00 => {
+                    channel.latched = Some(channel.count);
+                    return;
+                }
+                0b01 => Access::LowByte,
+                0b10 => Access::HighByte,
+                _ => Access::LowThenHigh,
+            };
+            return;
+        }
+
+        let channel = &mut self.channels[(port - Self::PORTS.start()) as usize];
+        if channel.next_byte_is_high() {
+            channel.reload = (channel.reload & 0x00FF) | (value as u16) << 8;
+        } else {
+            channel.reload = (channel.reload & 0xFF00) | value as u16;
+        }
+        channel.count = channel.reload;
+    }
+
+    fn tick(&mut self, clocks: u32) {
+        self.clocks += clocks;
+        let ticks = self.clocks / 4;
+        self.clocks %= 4;
+        for channel in &mut self.channels {
+            // a reload value of zero counts all 65536 steps
+            let period = if channel.reload == 0 {
+                0x10000
+            } else {
+                channel.reload as u32
+            };
+            let elapsed = ticks % period;
+            // a count of zero is still a full period away from reloading
+            let remaining = if channel.count == 0 {
+                period
+            } else {
+                channel.count as u32
+            };
+            let count = if elapsed < remaining {
+                remaining - elapsed
+            } else {
+                period - (elapsed - remaining)
+            };
+            channel.count = count as u16;
+        }
+    }
+}
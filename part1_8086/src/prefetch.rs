@@ -0,0 +1,226 @@
+//! Timing model of the bus interface unit, which fills the prefetch queue with instruction bytes
+//! while the execution unit is busy.
+//!
+//! The clocks of [`cycles`](crate::cycles) are the ones of the manual's tables, which assume every
+//! instruction is already waiting in the queue once the previous one finishes. Runs of short but
+//! fast instructions drain the queue faster than the bus refills it, every taken jump flushes it
+//! and memory operands keep the bus from fetching, so real hardware spends clocks waiting on
+//! instruction fetch which the tables don't show.
+
+use std::fmt::Write;
+
+use crate::exec::{MemoryCounters, MEMORY_SIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cpu {
+    /// 16 bit data bus and a 6 byte queue.
+    I8086,
+    /// 8 bit data bus and a 4 byte queue.
+    I8088,
+}
+
+impl Cpu {
+    pub fn queue_size(self) -> usize {
+        match self {
+            Cpu::I8086 => 6,
+            Cpu::I8088 => 4,
+        }
+    }
+
+    /// Instruction bytes a single bus cycle fetches from `address`.
+    fn fetch_width(self, address: usize) -> usize {
+        match self {
+            Cpu::I8086 if address.is_multiple_of(2) => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl std::fmt::Display for Cpu {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cpu::I8086 => f.write_str("8086"),
+            Cpu::I8088 => f.write_str("8088"),
+        }
+    }
+}
+
+/// Clocks of a bus cycle without any wait states.
+pub const BUS_CYCLE: u32 = 4;
+
+/// How the clocks of a single instruction came about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    /// Clocks from the start of the instruction until the next one can start.
+    pub clocks: u32,
+    /// Part of `clocks` the execution unit spent waiting for the bus, either for the bytes of the
+    /// instruction or for a fetch in progress to make way for its memory operands.
+    pub stalled: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct BusUnit {
+    pub cpu: Cpu,
+    /// Clocks slow memory adds to every bus cycle.
+    pub wait_states: u32,
+    /// Instruction bytes waiting in the queue.
+    queued: usize,
+    /// Address the next instruction byte is fetched from.
+    fetch_address: usize,
+    /// Clock the bus finishes its last cycle at.
+    bus_free: u64,
+    /// Clock the execution unit is at.
+    clock: u64,
+    /// Bus cycles spent fetching instructions.
+    pub fetches: u64,
+    /// Times a jump threw away the queue.
+    pub flushes: u64,
+    /// Clocks the execution unit spent waiting for the bus.
+    pub stalled: u64,
+}
+
+impl BusUnit {
+    /// Starts with an empty queue, about to fetch the instruction at `entry`.
+    pub fn new(cpu: Cpu, wait_states: u32, entry: usize) -> Self {
+        Self {
+            cpu,
+            wait_states,
+            queued: 0,
+            fetch_address: entry,
+            bus_free: 0,
+            clock: 0,
+            fetches: 0,
+            flushes: 0,
+            stalled: 0,
+        }
+    }
+
+    /// Clocks elapsed since the start of the simulation.
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    fn bus_cycle(&self) -> u64 {
+        (BUS_CYCLE + self.wait_states).into()
+    }
+
+    /// The 8086 only starts a fetch once a whole word fits into the queue.
+    fn has_room(&self) -> bool {
+        let free = self.cpu.queue_size() - self.queued;
+        match self.cpu {
+            Cpu::I8086 => free >= 2,
+            Cpu::I8088 => free >= 1,
+        }
+    }
+
+    /// Runs a single fetch on the bus, starting no earlier than `start`.
+    fn fetch(&mut self, start: u64) {
+        let bytes = self.cpu.fetch_width(self.fetch_address);
+        self.queued += bytes;
+        self.fetch_address = (self.fetch_address + bytes) % MEMORY_SIZE;
+        self.bus_free = self.bus_free.max(start) + self.bus_cycle();
+        self.fetches += 1;
+    }
+
+    /// Lets the bus fill the queue until `until`. A fetch started before then runs to completion.
+    fn prefetch(&mut self, until: u64) {
+        while self.bus_free < until && self.has_room() {
+            self.fetch(self.bus_free);
+        }
+        // an idle bus only picks up again once the execution unit makes room in the queue
+        self.bus_free = self.bus_free.max(until);
+    }
+
+    /// Accounts for an instruction `length` bytes long which takes `clocks` according to the
+    /// tables and made the memory `transfers`. `jump_target` is where control went if the
+    /// instruction transferred it.
+    pub fn execute(
+        &mut self,
+        length: usize,
+        clocks: u32,
+        transfers: MemoryCounters,
+        jump_target: Option<usize>,
+    ) -> Timing {
+        let start = self.clock;
+
+        // the execution unit takes the instruction bytes as they arrive
+        self.prefetch(self.clock);
+        let mut needed = length;
+        loop {
+            let taken = needed.min(self.queued);
+            self.queued -= taken;
+            needed -= taken;
+            if needed == 0 {
+                break;
+            }
+            self.fetch(self.clock);
+            self.clock = self.bus_free;
+        }
+        let fetched = self.clock;
+
+        // the tables only include the second bus cycle of unaligned words, which every word on
+        // the 8088 takes
+        let transferred = transfers.reads + transfers.writes;
+        let extra = match self.cpu {
+            Cpu::I8086 => transfers.unaligned_words,
+            Cpu::I8088 => transfers.words,
+        };
+        let bus_cycles = transferred + extra;
+        let mut clocks = u64::from(clocks) + bus_cycles * u64::from(self.wait_states);
+        if self.cpu == Cpu::I8088 {
+            clocks += (transfers.words - transfers.unaligned_words) * u64::from(BUS_CYCLE);
+        }
+
+        // the bus keeps fetching while the execution unit computes, up until it needs the bus for
+        // its own transfers, which a fetch in progress holds up
+        let mut end = self.clock + clocks;
+        if bus_cycles == 0 {
+            self.prefetch(end);
+        } else {
+            let operands = bus_cycles * self.bus_cycle();
+            self.prefetch(end.saturating_sub(operands).max(self.clock));
+            end = end.max(self.bus_free + operands);
+            self.bus_free = end;
+        }
+        self.clock = end;
+
+        if let Some(target) = jump_target {
+            self.queued = 0;
+            self.fetch_address = target;
+            self.flushes += 1;
+        }
+
+        let stalled = (fetched - start) + (end - fetched - clocks);
+        self.stalled += stalled;
+        Timing {
+            clocks: (end - start) as u32,
+            stalled: stalled as u32,
+        }
+    }
+
+    /// Describes the bus and how much it held up execution.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let mut line = |text: std::fmt::Arguments| {
+            report
+                .write_fmt(format_args!("{text}\n"))
+                .expect("can write into the report string");
+        };
+
+        line(format_args!(
+            "Bus: {}, {} byte queue, {} wait states",
+            self.cpu,
+            self.cpu.queue_size(),
+            self.wait_states
+        ));
+        line(format_args!(
+            "Clocks: {}, waiting for the bus: {}",
+            self.clock, self.stalled
+        ));
+        line(format_args!(
+            "Instruction fetches: {}, queue flushes: {}",
+            self.fetches, self.flushes
+        ));
+        report
+    }
+}
@@ -0,0 +1,309 @@
+//! Initial state of a simulation beyond the program itself: registers, flags and data placed in
+//! memory before the first instruction runs.
+//!
+//! The state is described in a small subset of TOML. Anything not mentioned starts out as zero.
+//!
+//! ```toml
+//! [registers]
+//! si = 0x1000
+//! cl = 16
+//! cs = 0x10
+//! ip = 0x100      # relative to cs
+//! flags = "CZ"    # the letters of the trace, C, P, A, Z, S and O
+//!
+//! [[load]]        # copies a file into memory, relative to the setup file
+//! address = 0x1000
+//! file = "input.bin"
+//!
+//! [[load]]        # or writes the bytes given
+//! address = 0x2000
+//! bytes = [1, 2, 0xFF]
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    exec::{State, MEMORY_SIZE},
+    Place, RegisterByte, RegisterWord, SegmentRegister, Width,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Data {
+    /// Path of the file, relative to the setup file.
+    File(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+/// Data placed into memory starting at `address`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Load {
+    pub address: usize,
+    pub data: Data,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Setup {
+    /// Register operands and the values they start out with.
+    pub registers: Vec<(Place, u16)>,
+    /// IP, the offset within the code segment to start at.
+    pub instruction_pointer: Option<u16>,
+    /// Flags set at the start, as the letters of [`Registers::flags_string`].
+    ///
+    /// [`Registers::flags_string`]: crate::exec::Registers::flags_string
+    pub flags: Option<String>,
+    pub loads: Vec<Load>,
+}
+
+/// A value on the right hand side of a `key = value` line.
+enum Value {
+    Integer(i64),
+    String(String),
+    Array(Vec<i64>),
+}
+
+/// The table a line belongs to.
+enum Table {
+    Top,
+    Registers,
+    /// An entry of the `[[load]]` array, with its keys so far.
+    Load {
+        address: Option<usize>,
+        data: Option<Data>,
+    },
+}
+
+impl Setup {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut setup = Self::default();
+        let mut table = Table::Top;
+        for (index, line) in text.lines().enumerate() {
+            let error = |what: String| format!("line {}: {what}", index + 1);
+            let line = strip_comment(line);
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                setup.finish(table).map_err(error)?;
+                table = match line {
+                    "[registers]" => Table::Registers,
+                    "[[load]]" => Table::Load {
+                        address: None,
+                        data: None,
+                    },
+                    _ => return Err(error(format!("unknown table `{line}`"))),
+                };
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected `key = value`".to_owned()))?;
+            let key = key.trim();
+            let value = parse_value(value.trim()).map_err(error)?;
+            match &mut table {
+                Table::Top => return Err(error(format!("`{key}` outside of a table"))),
+                Table::Registers => setup.set_register(key, value).map_err(error)?,
+                Table::Load { address, data } => match (key, value) {
+                    ("address", Value::Integer(value)) => {
+                        *address = Some(address_in_memory(value).map_err(error)?)
+                    }
+                    ("file", Value::String(path)) => *data = Some(Data::File(path.into())),
+                    ("bytes", Value::Array(values)) => {
+                        let bytes = values
+                            .into_iter()
+                            .map(|value| {
+                                u8::try_from(value)
+                                    .map_err(|_| format!("{value} does not fit into a byte"))
+                            })
+                            .collect::<Result<_, _>>()
+                            .map_err(error)?;
+                        *data = Some(Data::Bytes(bytes));
+                    }
+                    ("address" | "file" | "bytes", _) => {
+                        return Err(error(format!("wrong type of value for `{key}`")))
+                    }
+                    _ => return Err(error(format!("unknown key `{key}` in `[[load]]`"))),
+                },
+            }
+        }
+        setup
+            .finish(table)
+            .map_err(|error| format!("end of file: {error}"))?;
+        Ok(setup)
+    }
+
+    /// Adds the `[[load]]` entry being parsed once its table ends.
+    fn finish(&mut self, table: Table) -> Result<(), String> {
+        match table {
+            Table::Load {
+                address: Some(address),
+                data: Some(data),
+            } => self.loads.push(Load { address, data }),
+            Table::Load { address: None, .. } => {
+                return Err("`[[load]]` without an `address`".to_owned())
+            }
+            Table::Load { data: None, .. } => {
+                return Err("`[[load]]` without a `file` or `bytes`".to_owned())
+            }
+            Table::Top | Table::Registers => (),
+        }
+        Ok(())
+    }
+
+    fn set_register(&mut self, key: &str, value: Value) -> Result<(), String> {
+        match (key, value) {
+            ("flags", Value::String(flags)) => {
+                if let Some(flag) = flags.chars().find(|flag| !"CPAZSO".contains(*flag)) {
+                    return Err(format!("unknown flag `{flag}`, expected some of CPAZSO"));
+                }
+                self.flags = Some(flags);
+            }
+            ("ip", Value::Integer(value)) => {
+                let offset =
+                    u16::try_from(value).map_err(|_| format!("{value} does not fit into `ip`"))?;
+                self.instruction_pointer = Some(offset);
+            }
+            (name, Value::Integer(value)) => {
+                let place = register(name).ok_or_else(|| format!("unknown register `{name}`"))?;
+                let width = register_width(place);
+                if !(-i64::from(width.sign_bit())..=i64::from(width.mask())).contains(&value) {
+                    return Err(format!("{value} does not fit into `{name}`"));
+                }
+                self.registers.push((place, value as u16));
+            }
+            (name, _) => return Err(format!("`{name}` expects a number")),
+        }
+        Ok(())
+    }
+
+    /// Sets up `state`, which already holds the program. `base` is the directory the paths of
+    /// files to load are relative to.
+    pub fn apply(&self, state: &mut State, base: &Path) -> Result<(), String> {
+        for load in &self.loads {
+            let bytes = match &load.data {
+                Data::File(path) => {
+                    let path = base.join(path);
+                    std::fs::read(&path)
+                        .map_err(|error| format!("failed to read {}: {error}", path.display()))?
+                }
+                Data::Bytes(bytes) => bytes.clone(),
+            };
+            state
+                .load_at(load.address, &bytes)
+                .map_err(|error| format!("failed to load at {:#06x}: {error}", load.address))?;
+        }
+        for &(place, value) in &self.registers {
+            state.write(place, register_width(place), value);
+        }
+        if let Some(flags) = &self.flags {
+            state.registers.set_flags_string(flags);
+        }
+        let sets_code_segment = self
+            .registers
+            .iter()
+            .any(|&(place, _)| place == Place::Segment(SegmentRegister::CS));
+        if sets_code_segment || self.instruction_pointer.is_some() {
+            let offset = self.instruction_pointer.unwrap_or(0);
+            state.jump_far(state.registers[SegmentRegister::CS], offset);
+        }
+        Ok(())
+    }
+}
+
+/// The register operand called `name`, in any case.
+fn register(name: &str) -> Option<Place> {
+    let named = |place: Place| {
+        let place_name = match place {
+            Place::Byte(register) => format!("{register:?}"),
+            Place::Word(register) => format!("{register:?}"),
+            Place::Segment(register) => format!("{register:?}"),
+            Place::Adress(_) => unreachable!("only registers are named"),
+        };
+        place_name.eq_ignore_ascii_case(name).then_some(place)
+    };
+    (0..8)
+        .filter_map(RegisterWord::from_octal)
+        .map(Place::Word)
+        .chain((0..8).filter_map(RegisterByte::from_octal).map(Place::Byte))
+        .chain(
+            [
+                SegmentRegister::ES,
+                SegmentRegister::CS,
+                SegmentRegister::SS,
+                SegmentRegister::DS,
+            ]
+            .map(Place::Segment),
+        )
+        .find_map(named)
+}
+
+fn register_width(place: Place) -> Width {
+    match place {
+        Place::Byte(_) => Width::Byte,
+        _ => Width::Word,
+    }
+}
+
+fn address_in_memory(value: i64) -> Result<usize, String> {
+    usize::try_from(value)
+        .ok()
+        .filter(|&address| address < MEMORY_SIZE)
+        .ok_or_else(|| format!("address {value} is outside of memory"))
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    if let Some(string) = text.strip_prefix('"') {
+        let string = string
+            .strip_suffix('"')
+            .filter(|string| !string.contains('"'))
+            .ok_or_else(|| format!("unterminated string `{text}`"))?;
+        return Ok(Value::String(string.to_owned()));
+    }
+    if let Some(items) = text.strip_prefix('[') {
+        let items = items
+            .strip_suffix(']')
+            .ok_or_else(|| format!("unterminated array `{text}`"))?;
+        return items
+            .split(',')
+            .map(str::trim)
+            // a trailing comma is fine
+            .filter(|item| !item.is_empty())
+            .map(parse_integer)
+            .collect::<Result<_, _>>()
+            .map(Value::Array);
+    }
+    parse_integer(text).map(Value::Integer)
+}
+
+/// Parses a decimal, `0x` hexadecimal, `0o` octal or `0b` binary integer, optionally signed and
+/// with `_` between digits.
+fn parse_integer(text: &str) -> Result<i64, String> {
+    let digits = text.replace('_', "");
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, digits.strip_prefix('+').unwrap_or(&digits)),
+    };
+    let (radix, digits) = match digits.get(..2) {
+        Some("0x") => (16, &digits[2..]),
+        Some("0o") => (8, &digits[2..]),
+        Some("0b") => (2, &digits[2..]),
+        _ => (10, digits),
+    };
+    let value =
+        i64::from_str_radix(digits, radix).map_err(|_| format!("invalid value `{text}`"))?;
+    Ok(if negative { -value } else { value })
+}
+
+/// Cuts off a `#` comment, unless it is inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return line[..index].trim(),
+            _ => (),
+        }
+    }
+    line.trim()
+}
@@ -0,0 +1,276 @@
+//! Execution statistics gathered while simulating, to show where a program spends its time, and
+//! the static instruction mix of a binary, to show how it uses the instruction set.
+
+use std::{cmp::Reverse, collections::BTreeMap, fmt::Write};
+
+use crate::{decode, exec::MemoryCounters, Branch, Instruction, Place, Port, Width};
+
+#[derive(Debug, Clone, Copy)]
+pub struct AddressStatistics {
+    pub instruction: Instruction,
+    pub executions: u64,
+    pub cycles: u64,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Statistics {
+    /// Estimate clocks with the [`cycles`](crate::cycles) model.
+    pub count_cycles: bool,
+    pub addresses: BTreeMap<usize, AddressStatistics>,
+    pub mnemonics: BTreeMap<&'static str, u64>,
+    pub memory_reads: u64,
+    pub memory_writes: u64,
+    pub branches_taken: u64,
+    pub branches_not_taken: u64,
+    pub cycles: u64,
+}
+
+impl Statistics {
+    pub fn new(count_cycles: bool) -> Self {
+        Self {
+            count_cycles,
+            ..Default::default()
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.mnemonics.values().sum()
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        address: usize,
+        instruction: &Instruction,
+        jumped: bool,
+        clocks: u32,
+        (counters_prior, counters_after): (MemoryCounters, MemoryCounters),
+    ) {
+        *self.mnemonics.entry(instruction.mnemonic()).or_default() += 1;
+        let transfers = counters_after - counters_prior;
+        self.memory_reads += transfers.reads;
+        self.memory_writes += transfers.writes;
+        if let Instruction::Jump { .. } | Instruction::Loop { .. } = instruction {
+            if jumped {
+                self.branches_taken += 1;
+            } else {
+                self.branches_not_taken += 1;
+            }
+        }
+
+        let cycles = clocks as u64;
+        self.cycles += cycles;
+
+        let entry = self.addresses.entry(address).or_insert(AddressStatistics {
+            instruction: *instruction,
+            executions: 0,
+            cycles: 0,
+        });
+        entry.instruction = *instruction;
+        entry.executions += 1;
+        entry.cycles += cycles;
+    }
+
+    /// Renders the totals, followed by the mnemonics and addresses executed most often.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let mut line = |text: std::fmt::Arguments| {
+            report
+                .write_fmt(format_args!("{text}\n"))
+                .expect("can write into the report string");
+        };
+
+        line(format_args!(
+            "Instructions executed: {}",
+            self.instructions()
+        ));
+        line(format_args!(
+            "Memory reads: {}, writes: {}",
+            self.memory_reads, self.memory_writes
+        ));
+        line(format_args!(
+            "Branches taken: {}, not taken: {}",
+            self.branches_taken, self.branches_not_taken
+        ));
+        if self.count_cycles {
+            line(format_args!("Cycles: {}", self.cycles));
+        }
+
+        line(format_args!("\nPer mnemonic:"));
+        let mut mnemonics: Vec<_> = self.mnemonics.iter().collect();
+        mnemonics.sort_by_key(|(_, count)| Reverse(**count));
+        for (mnemonic, count) in mnemonics {
+            line(format_args!("  {mnemonic:<8} {count:>8}"));
+        }
+
+        line(format_args!("\nPer address:"));
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses
+            .sort_by_key(|(_, statistics)| Reverse((statistics.cycles, statistics.executions)));
+        for (address, statistics) in addresses {
+            let AddressStatistics {
+                instruction,
+                executions,
+                cycles,
+            } = statistics;
+            if self.count_cycles {
+                line(format_args!(
+                    "  {address:04x} {executions:>8} {cycles:>8}  {instruction}"
+                ));
+            } else {
+                line(format_args!(
+                    "  {address:04x} {executions:>8}  {instruction}"
+                ));
+            }
+        }
+        report
+    }
+}
+
+/// Number and total size of the instructions falling into a category.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Tally {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+/// How often a binary uses the instructions, addressing modes and operand widths, without running
+/// it.
+#[derive(Debug, Default, Clone)]
+pub struct InstructionMix {
+    pub mnemonics: BTreeMap<&'static str, Tally>,
+    /// See [`addressing_mode`] for the names of the modes.
+    pub addressing_modes: BTreeMap<&'static str, Tally>,
+    /// `byte`, `word`, or `none` for instructions without data operands.
+    pub widths: BTreeMap<&'static str, Tally>,
+}
+
+impl InstructionMix {
+    /// Decodes `code` from start to end, the way the disassembly does.
+    pub fn from_code(mut code: &[u8]) -> Self {
+        let mut mix = Self::default();
+        while !code.is_empty() {
+            let (length, instruction) = decode::single_instruction(&mut code);
+            let width = match instruction {
+                Instruction::Unrecognized(_) => "unrecognized",
+                _ if !has_data_operands(&instruction) => "none",
+                _ => match instruction.width() {
+                    Width::Byte => "byte",
+                    Width::Word => "word",
+                },
+            };
+            for (tallies, category) in [
+                (&mut mix.mnemonics, instruction.mnemonic()),
+                (&mut mix.addressing_modes, addressing_mode(&instruction)),
+                (&mut mix.widths, width),
+            ] {
+                let tally = tallies.entry(category).or_default();
+                tally.count += 1;
+                tally.bytes += length as u64;
+            }
+        }
+        mix
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.mnemonics.values().map(|tally| tally.count).sum()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.mnemonics.values().map(|tally| tally.bytes).sum()
+    }
+
+    /// Renders the totals, followed by the count and bytes of every category, most used first.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let mut line = |text: std::fmt::Arguments| {
+            report
+                .write_fmt(format_args!("{text}\n"))
+                .expect("can write into the report string");
+        };
+
+        line(format_args!(
+            "Instructions: {}, bytes: {}",
+            self.instructions(),
+            self.bytes()
+        ));
+        for (title, tallies) in [
+            ("Per mnemonic:", &self.mnemonics),
+            ("Per addressing mode:", &self.addressing_modes),
+            ("Per operand width:", &self.widths),
+        ] {
+            line(format_args!("\n{title:<20} {:>8}{:>8}", "count", "bytes"));
+            let mut tallies: Vec<_> = tallies.iter().collect();
+            tallies.sort_by_key(|(_, tally)| Reverse((tally.count, tally.bytes)));
+            for (category, Tally { count, bytes }) in tallies {
+                line(format_args!("  {category:<18} {count:>8}{bytes:>8}"));
+            }
+        }
+        report
+    }
+}
+
+/// The addressing mode of the operand that determines the instruction's encoding and timing most:
+/// a memory operand, being `direct`, `indirect` through registers or `indirect+disp` with a
+/// displacement, then `immediate` and `register` operands. Instructions without any of those are
+/// `relative` or `far` branches, or `implied`.
+pub fn addressing_mode(instruction: &Instruction) -> &'static str {
+    let (places, immediate) = match *instruction {
+        Instruction::Arithmetic { target, source, .. }
+        | Instruction::Test { target, source }
+        | Instruction::Xchg { target, source }
+        | Instruction::Load { target, source, .. }
+        | Instruction::Mov { target, source } => ([Some(target), Some(source)], false),
+        Instruction::ArithmeticImmediate { target, .. }
+        | Instruction::TestImmediate { target, .. }
+        | Instruction::MovImmediate { target, .. } => ([Some(target), None], true),
+        Instruction::Shift { target, .. }
+        | Instruction::Unary { target, .. }
+        | Instruction::Push { target }
+        | Instruction::Pop { target } => ([Some(target), None], false),
+        Instruction::Call(branch) | Instruction::Jmp(branch) => match branch {
+            Branch::Short(_) | Branch::Near(_) => return "relative",
+            Branch::Far { .. } => return "far",
+            Branch::Indirect(target) | Branch::FarIndirect(target) => ([Some(target), None], false),
+        },
+        Instruction::Jump { .. } | Instruction::Loop { .. } => return "relative",
+        Instruction::In { port, .. } | Instruction::Out { port, .. } => match port {
+            Port::Immediate(_) => ([None, None], true),
+            Port::Dx => return "register",
+        },
+        Instruction::Interrupt(_) | Instruction::Return { pop: Some(_), .. } => {
+            ([None, None], true)
+        }
+        Instruction::Return { pop: None, .. }
+        | Instruction::StringOperation { .. }
+        | Instruction::Implied(_) => ([None, None], false),
+        Instruction::Unrecognized(_) => return "unrecognized",
+    };
+
+    let memory = places.iter().flatten().find_map(|place| match place {
+        Place::Adress(address) => Some(address),
+        _ => None,
+    });
+    match memory {
+        Some(address) if address.is_direct() => "direct",
+        Some(address) if address.displacement_width().is_none() => "indirect",
+        Some(_) => "indirect+disp",
+        None if immediate => "immediate",
+        None if places.iter().any(Option::is_some) => "register",
+        None => "implied",
+    }
+}
+
+/// Whether the instruction works on data in registers or memory of a certain width, unlike
+/// branches and most implied instructions.
+fn has_data_operands(instruction: &Instruction) -> bool {
+    !matches!(
+        instruction,
+        Instruction::Jump { .. }
+            | Instruction::Loop { .. }
+            | Instruction::Call(_)
+            | Instruction::Jmp(_)
+            | Instruction::Return { .. }
+            | Instruction::Interrupt(_)
+            | Instruction::Implied(_)
+    )
+}
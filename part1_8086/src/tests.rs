@@ -1,6 +1,6 @@
 use std::io::Write;
 
-use crate::{decode, read_listing};
+use crate::{decode, exec, exec::State, read_listing};
 
 const LISTING_DIRECTORY: &str = "../course_reference/perfaware/part1";
 fn process_file_listing(listing_name: &str) {
@@ -38,6 +38,14 @@ fn assemble(disassembly: String, base_name: &str) -> (Vec<u8>, usize) {
     (new_memory, new_program_length)
 }
 
+/// Load `program` into a fresh state and run it to completion.
+fn run_program(program: &[u8]) -> State {
+    let mut state = State::default();
+    state.load_bytes(program);
+    exec::all_instructions(&mut state).expect("program runs to completion");
+    state
+}
+
 mod decoding {
     use crate::tests::process_file_listing;
 
@@ -72,29 +80,2294 @@ mod decoding {
     }
 }
 
+mod shifts {
+    use crate::{decode, tests::run_program, RegisterByte::*, RegisterWord::*};
+
+    #[test]
+    fn decode_shift_forms() {
+        let disassembly = decode::all_instructions(&[0xD1, 0xE0, 0xD3, 0x2F, 0xD0, 0xD8]);
+        assert_eq!(
+            disassembly,
+            "bits 16\nshl AX, 1\nshr word [BX], CL\nrcr AL, 1\n"
+        );
+    }
+
+    #[test]
+    fn shl_word_by_one() {
+        // mov ax, 0x8001; shl ax, 1
+        let state = run_program(&[0xB8, 0x01, 0x80, 0xD1, 0xE0]);
+        assert_eq!(state.registers[AX], 2);
+        assert!(state.registers.flag_carry);
+        assert!(state.registers.flag_overflow);
+        assert!(!state.registers.flag_zero);
+    }
+
+    #[test]
+    fn sar_word_by_cl() {
+        // mov ax, 0x8000; mov cl, 3; sar ax, cl
+        let state = run_program(&[0xB8, 0x00, 0x80, 0xB1, 0x03, 0xD3, 0xF8]);
+        assert_eq!(state.registers[AX], 0xF000);
+        assert!(!state.registers.flag_carry);
+        assert!(!state.registers.flag_overflow);
+        assert!(state.registers.flag_sign);
+    }
+
+    #[test]
+    fn rcr_byte_through_carry() {
+        // mov al, 0x80; shl al, 1; rcr al, 1
+        let state = run_program(&[0xB0, 0x80, 0xD0, 0xE0, 0xD0, 0xD8]);
+        assert_eq!(state.registers.byte(AL), 0x80);
+        assert!(!state.registers.flag_carry);
+        assert!(state.registers.flag_overflow);
+    }
+
+    #[test]
+    fn rol_byte_in_memory() {
+        // mov byte [bx], 0x81; rol byte [bx], 1
+        let state = run_program(&[0xC6, 0x07, 0x81, 0xD0, 0x07]);
+        assert_eq!(state.memory[0], 0x03);
+        assert!(state.registers.flag_carry);
+        assert!(state.registers.flag_overflow);
+    }
+}
+
+mod logic {
+    use crate::{decode, tests::run_program, RegisterByte::*, RegisterWord::*};
+
+    #[test]
+    fn decode_logic_forms() {
+        let disassembly = decode::all_instructions(&[
+            0x21, 0xD8, 0x09, 0xD8, 0x31, 0xD8, 0x85, 0xD8, 0xA8, 0x01, 0xF6, 0xD8, 0xF7, 0xD0,
+        ]);
+        assert_eq!(
+            disassembly,
+            "bits 16\nand AX, BX\nor AX, BX\nxor AX, BX\ntest AX, BX\ntest AL, 1\nneg AL\nnot AX\n"
+        );
+    }
+
+    #[test]
+    fn register_register() {
+        // mov ax, 0x0FF0; mov bx, 0x00FF; mov cx, ax; and cx, bx; mov dx, ax; xor dx, bx; or ax, bx
+        let state = run_program(&[
+            0xB8, 0xF0, 0x0F, 0xBB, 0xFF, 0x00, 0x89, 0xC1, 0x21, 0xD9, 0x89, 0xC2, 0x31, 0xDA,
+            0x09, 0xD8,
+        ]);
+        assert_eq!(state.registers[CX], 0x00F0);
+        assert_eq!(state.registers[DX], 0x0F0F);
+        assert_eq!(state.registers[AX], 0x0FFF);
+        assert!(!state.registers.flag_carry);
+        assert!(!state.registers.flag_overflow);
+    }
+
+    #[test]
+    fn register_memory() {
+        // mov word [bx], 0x0F0F; mov ax, 0x00FF; and ax, [bx]; or [bx], ax
+        let state = run_program(&[
+            0xC7, 0x07, 0x0F, 0x0F, 0xB8, 0xFF, 0x00, 0x23, 0x07, 0x09, 0x07,
+        ]);
+        assert_eq!(state.registers[AX], 0x000F);
+        assert_eq!(&state.memory[0..2], &[0x0F, 0x0F]);
+    }
+
+    #[test]
+    fn accumulator_immediate() {
+        // mov al, 0x34; and al, 0x0F
+        let state = run_program(&[0xB0, 0x34, 0x24, 0x0F]);
+        assert_eq!(state.registers.byte(AL), 0x04);
+        assert!(!state.registers.flag_parity);
+
+        // mov ax, 0x1234; xor ax, 0xFFFF
+        let state = run_program(&[0xB8, 0x34, 0x12, 0x35, 0xFF, 0xFF]);
+        assert_eq!(state.registers[AX], 0xEDCB);
+        assert!(state.registers.flag_sign);
+        assert!(!state.registers.flag_zero);
+    }
+
+    #[test]
+    fn add_with_carry_and_subtract_with_borrow() {
+        // mov ax, 0xFFFF; add ax, 1; mov dx, 0; adc dx, 0
+        let state = run_program(&[
+            0xB8, 0xFF, 0xFF, 0x05, 0x01, 0x00, 0xBA, 0x00, 0x00, 0x81, 0xD2, 0x00, 0x00,
+        ]);
+        assert_eq!(state.registers[AX], 0);
+        assert_eq!(state.registers[DX], 1);
+        assert!(!state.registers.flag_carry);
+
+        // mov bx, 0; sub bx, 1; mov cx, 0; sbb cx, 0
+        let state = run_program(&[
+            0xBB, 0x00, 0x00, 0x81, 0xEB, 0x01, 0x00, 0xB9, 0x00, 0x00, 0x81, 0xD9, 0x00, 0x00,
+        ]);
+        assert_eq!(state.registers[BX], 0xFFFF);
+        assert_eq!(state.registers[CX], 0xFFFF);
+        assert!(state.registers.flag_carry);
+        assert!(state.registers.flag_auxiliary_carry);
+    }
+
+    #[test]
+    fn not_and_neg() {
+        // mov al, 1; neg al
+        let state = run_program(&[0xB0, 0x01, 0xF6, 0xD8]);
+        assert_eq!(state.registers.byte(AL), 0xFF);
+        assert!(state.registers.flag_carry);
+        assert!(state.registers.flag_sign);
+
+        // mov ax, 0x00FF; not ax
+        let state = run_program(&[0xB8, 0xFF, 0x00, 0xF7, 0xD0]);
+        assert_eq!(state.registers[AX], 0xFF00);
+        assert!(!state.registers.flag_sign);
+    }
+
+    #[test]
+    fn test_leaves_operands_alone() {
+        // mov byte [bx], 0x80; test byte [bx], 0x80
+        let state = run_program(&[0xC6, 0x07, 0x80, 0xF6, 0x07, 0x80]);
+        assert_eq!(state.memory[0], 0x80);
+        assert!(state.registers.flag_sign);
+        assert!(!state.registers.flag_zero);
+    }
+}
+
+mod sign_extension {
+    use crate::{decode, tests::run_program, RegisterByte::*, RegisterWord::*};
+
+    #[test]
+    fn decode_signed_immediates() {
+        let disassembly = decode::all_instructions(&[
+            0x83, 0x07, 0xFD, 0x80, 0x07, 0xFD, 0x83, 0xE8, 0xFF, 0x04, 0x80,
+        ]);
+        assert_eq!(
+            disassembly,
+            "bits 16\nadd word [BX], -3\nadd byte [BX], -3\nsub AX, -1\nadd AL, -128\n"
+        );
+    }
+
+    #[test]
+    fn add_word_negative_byte_immediate() {
+        // mov word [bx], 10; add word [bx], -3
+        let state = run_program(&[0xC7, 0x07, 0x0A, 0x00, 0x83, 0x07, 0xFD]);
+        assert_eq!(&state.memory[0..2], &[0x07, 0x00]);
+        assert!(state.registers.flag_carry);
+        assert!(!state.registers.flag_sign);
+    }
+
+    #[test]
+    fn sign_extended_immediate_reaches_high_byte() {
+        // mov ax, 0x00FF; sub ax, -1; cmp ax, -256
+        let state = run_program(&[0xB8, 0xFF, 0x00, 0x83, 0xE8, 0xFF, 0x83, 0xF8, 0x00]);
+        assert_eq!(state.registers[AX], 0x0100);
+        assert!(!state.registers.flag_zero);
+
+        // mov al, 2; add al, -3
+        let state = run_program(&[0xB0, 0x02, 0x04, 0xFD]);
+        assert_eq!(state.registers.byte(AL), 0xFF);
+        assert!(state.registers.flag_sign);
+        assert!(!state.registers.flag_carry);
+    }
+}
+
+mod displacement {
+    use crate::{decode, tests::run_program, RegisterWord::*};
+
+    #[test]
+    fn decode_signed_displacements() {
+        let disassembly = decode::all_instructions(&[
+            0x8B, 0x47, 0xFE, 0x8B, 0x46, 0x00, 0x8B, 0x00, 0x89, 0x8C, 0xD4, 0xFE, 0x88, 0x6B,
+            0x80, 0x8B, 0x1E, 0xFE, 0xFF,
+        ]);
+        assert_eq!(
+            disassembly,
+            "bits 16
+mov AX, [BX - 2]
+mov AX, [BP]
+mov AX, [BX + SI]
+mov [SI - 300], CX
+mov [BP + DI - 128], CH
+mov BX, [65534]
+"
+        );
+    }
+
+    #[test]
+    fn negative_displacements_address_below_the_base() {
+        // mov bx, 0x10; mov word [bx - 2], 0x1234; mov ax, [bx - 3]
+        let state = run_program(&[
+            0xBB, 0x10, 0x00, 0xC7, 0x47, 0xFE, 0x34, 0x12, 0x8B, 0x47, 0xFD,
+        ]);
+        assert_eq!(&state.memory[0x0E..0x10], &[0x34, 0x12]);
+        assert_eq!(state.registers[AX], 0x3400);
+    }
+
+    #[test]
+    fn addresses_wrap_around_the_segment() {
+        // mov bx, 1; mov word [bx - 2], 0x1234; mov si, 0xFFFF; mov cx, [si + 1]
+        let state = run_program(&[
+            0xBB, 0x01, 0x00, 0xC7, 0x47, 0xFE, 0x34, 0x12, 0xBE, 0xFF, 0xFF, 0x8B, 0x4C, 0x01,
+        ]);
+        // the high byte of the word at 0xFFFF lands on address 0, over the first opcode
+        assert_eq!(state.memory[0xFFFF], 0x34);
+        assert_eq!(state.memory[0], 0x12);
+        assert_eq!(state.registers[CX], 0x0112);
+    }
+}
+
+mod segments {
+    use crate::{
+        decode,
+        exec::{self, State},
+        stats::Statistics,
+        tests::run_program,
+        Instruction,
+        RegisterByte::*,
+        RegisterWord::*,
+        SegmentRegister::*,
+    };
+
+    #[test]
+    fn decode_accumulator_and_segment_moves() {
+        let disassembly = decode::all_instructions(&[
+            0xA0, 0xE8, 0x03, 0xA1, 0x02, 0x00, 0xA2, 0x03, 0x00, 0xA3, 0xFF, 0xFF, 0x8E, 0xD8,
+            0x8C, 0xC3, 0x8C, 0x1E, 0x10, 0x00, 0x8E, 0x56, 0xFE,
+        ]);
+        assert_eq!(
+            disassembly,
+            "bits 16
+mov AL, [1000]
+mov AX, [2]
+mov [3], AL
+mov [65535], AX
+mov DS, AX
+mov BX, ES
+mov [16], DS
+mov SS, [BP - 2]
+"
+        );
+
+        // only four segment registers fit into the reg field
+        let (length, instruction) = decode::single_instruction(&mut &[0x8E, 0xE0][..]);
+        assert_eq!((length, instruction), (1, Instruction::Unrecognized(0x8E)));
+    }
+
+    #[test]
+    fn accumulator_moves() {
+        // mov ax, 0x1234; mov [0x100], ax; mov al, [0x101]; mov [0x102], al
+        let state = run_program(&[
+            0xB8, 0x34, 0x12, 0xA3, 0x00, 0x01, 0xA0, 0x01, 0x01, 0xA2, 0x02, 0x01,
+        ]);
+        assert_eq!(&state.memory[0x100..0x103], &[0x34, 0x12, 0x12]);
+        assert_eq!(state.registers.byte(AL), 0x12);
+    }
+
+    #[test]
+    fn segment_moves() {
+        // mov ax, 0x2222; mov bx, 0x4444; mov cx, 0x6666; mov ss, ax; mov ds, bx; mov es, cx
+        // mov sp, ss; mov bp, ds; mov si, es; mov [16], ds
+        let state = run_program(&[
+            0xB8, 0x22, 0x22, 0xBB, 0x44, 0x44, 0xB9, 0x66, 0x66, 0x8E, 0xD0, 0x8E, 0xDB, 0x8E,
+            0xC1, 0x8C, 0xD4, 0x8C, 0xDD, 0x8C, 0xC6, 0x8C, 0x1E, 0x10, 0x00,
+        ]);
+        assert_eq!(state.registers[SS], 0x2222);
+        assert_eq!(state.registers[DS], 0x4444);
+        assert_eq!(state.registers[ES], 0x6666);
+        assert_eq!(state.registers[CS], 0);
+        assert_eq!(state.registers[SP], 0x2222);
+        assert_eq!(state.registers[BP], 0x4444);
+        assert_eq!(state.registers[SI], 0x6666);
+        // DS:16
+        assert_eq!(&state.memory[0x44450..0x44452], &[0x44, 0x44]);
+    }
+
+    #[test]
+    fn addresses_memory_through_segments() {
+        // mov ax, 0x100; mov ss, ax; mov bp, 0xFE; mov word [bp], 0x1234; mov cx, [bp];
+        // mov dx, ds:[bp]; lea si, [bp + 2]; mov ds, ax; mov [2], ax
+        let state = run_program(&[
+            0xB8, 0x00, 0x01, 0x8E, 0xD0, 0xBD, 0xFE, 0x00, 0xC7, 0x46, 0x00, 0x34, 0x12, 0x8B,
+            0x4E, 0x00, 0x3E, 0x8B, 0x56, 0x00, 0x8D, 0x76, 0x02, 0x8E, 0xD8, 0xA3, 0x02, 0x00,
+        ]);
+        // BP addresses the stack segment unless overridden
+        assert_eq!(&state.memory[0x10FE..0x1100], &[0x34, 0x12]);
+        assert_eq!(state.registers[CX], 0x1234);
+        assert_eq!(state.registers[DX], 0);
+        // lea loads the offset alone
+        assert_eq!(state.registers[SI], 0x100);
+        assert_eq!(&state.memory[0x1002..0x1004], &[0x00, 0x01]);
+    }
+
+    #[test]
+    fn estimates_accumulator_and_segment_moves() {
+        // mov ax, [2]; mov [4], al; mov ds, ax; mov [16], ds; mov bx, [2]
+        let mut state = State::default();
+        state.load_bytes(&[
+            0xA1, 0x02, 0x00, 0xA2, 0x04, 0x00, 0x8E, 0xD8, 0x8C, 0x1E, 0x10, 0x00, 0x8B, 0x1E,
+            0x02, 0x00,
+        ]);
+        state.statistics = Some(Statistics::new(true));
+        exec::all_instructions(&mut state).unwrap();
+        let statistics = state.statistics.unwrap();
+        let cycles: Vec<_> = statistics.addresses.values().map(|a| a.cycles).collect();
+        // only the accumulator has the short form, everything else pays for the direct address
+        assert_eq!(cycles, [10, 10, 2, 9 + 6, 8 + 6]);
+    }
+}
+
+mod data_transfer {
+    use crate::{
+        cycles, decode, tests::run_program, RegisterByte::*, RegisterWord::*, SegmentRegister::*,
+    };
+
+    #[test]
+    fn exchanges_and_loads() {
+        // mov ax, 1; mov bx, 2; xchg ax, bx; mov cx, 0x4321; mov word [1000], 0x1234;
+        // xchg cx, [1000]; lea si, [bx + di + 5]; mov word [1002], 0x5678; les di, [1000];
+        // lds dx, [bp + 1000]
+        let state = run_program(&[
+            0xB8, 0x01, 0x00, 0xBB, 0x02, 0x00, 0x93, 0xB9, 0x21, 0x43, 0xC7, 0x06, 0xE8, 0x03,
+            0x34, 0x12, 0x87, 0x0E, 0xE8, 0x03, 0x8D, 0x71, 0x05, 0xC7, 0x06, 0xEA, 0x03, 0x78,
+            0x56, 0xC4, 0x3E, 0xE8, 0x03, 0xC5, 0x96, 0xE8, 0x03,
+        ]);
+        let registers = &state.registers;
+        assert_eq!((registers[AX], registers[BX]), (2, 1));
+        assert_eq!(registers[CX], 0x1234);
+        assert_eq!(&state.memory[1000..1002], &[0x21, 0x43]);
+        assert_eq!(registers[SI], 1 + 5);
+        assert_eq!((registers[ES], registers[DI]), (0x5678, 0x4321));
+        assert_eq!((registers[DS], registers[DX]), (0x5678, 0x4321));
+    }
+
+    #[test]
+    fn translate_convert_and_flag_transfers() {
+        // mov bx, 256; mov byte [259], 42; mov al, 3; xlat; mov al, 0x80; cbw; mov cx, ax;
+        // mov ax, 0x8000; cwd; mov ah, 0xD5; sahf; mov ah, 0; lahf
+        let state = run_program(&[
+            0xBB, 0x00, 0x01, 0xC6, 0x06, 0x03, 0x01, 0x2A, 0xB0, 0x03, 0xD7, 0xB0, 0x80, 0x98,
+            0x89, 0xC1, 0xB8, 0x00, 0x80, 0x99, 0xB4, 0xD5, 0x9E, 0xB4, 0x00, 0x9F,
+        ]);
+        let registers = &state.registers;
+        assert_eq!(registers[CX], 0xFF80);
+        assert_eq!(registers[DX], 0xFFFF);
+        assert_eq!(registers.flags_string(), "CPAZS");
+        // bit 1 of the flags is always set
+        assert_eq!(registers.byte(AH), 0xD7);
+
+        // xlat on its own
+        let state = run_program(&[
+            0xBB, 0x00, 0x01, 0xC6, 0x06, 0x03, 0x01, 0x2A, 0xB0, 0x03, 0xD7,
+        ]);
+        assert_eq!(state.registers.byte(AL), 42);
+        assert_eq!(state.memory_counters.reads, 1);
+    }
+
+    #[test]
+    fn data_transfer_clocks() {
+        let clocks = |bytes: &[u8]| {
+            let (_, instruction) = decode::single_instruction(&mut &bytes[..]);
+            cycles::estimate(&instruction, false, 0)
+        };
+        // xchg ax, bx; xchg cx, dx; xchg cx, [1000]
+        assert_eq!(clocks(&[0x93]), Some(3));
+        assert_eq!(clocks(&[0x87, 0xCA]), Some(4));
+        assert_eq!(clocks(&[0x87, 0x0E, 0xE8, 0x03]), Some(17 + 6));
+        // xchg cx, es:[1000] pays for the segment override
+        assert_eq!(clocks(&[0x26, 0x87, 0x0E, 0xE8, 0x03]), Some(17 + 6 + 2));
+        // lea si, [bx + di + 5]; les di, [1000]
+        assert_eq!(clocks(&[0x8D, 0x71, 0x05]), Some(2 + 12));
+        assert_eq!(clocks(&[0xC4, 0x3E, 0xE8, 0x03]), Some(16 + 6));
+        // xlat, lahf, sahf, cbw, cwd
+        assert_eq!(clocks(&[0xD7]), Some(11));
+        assert_eq!(clocks(&[0x9F]), Some(4));
+        assert_eq!(clocks(&[0x9E]), Some(4));
+        assert_eq!(clocks(&[0x98]), Some(2));
+        assert_eq!(clocks(&[0x99]), Some(5));
+    }
+}
+
+mod far_transfers {
+    use crate::{
+        cycles, decode,
+        exec::{self, State},
+        RegisterWord::*,
+        SegmentRegister::*,
+    };
+
+    #[test]
+    fn calls_jumps_and_returns_between_segments() {
+        let mut program = vec![0; 0x63];
+        let mut place = |address: usize, bytes: &[u8]| {
+            program[address..address + bytes.len()].copy_from_slice(bytes)
+        };
+        // mov sp, 0x100; call 2:0; mov bx, 0x40; jmp far [bx]
+        place(0x00, &[0xBC, 0x00, 0x01, 0x9A, 0x00, 0x00, 0x02, 0x00]);
+        place(0x08, &[0xBB, 0x40, 0x00, 0xFF, 0x2F]);
+        // at 2:0, mov ax, 1; retf
+        place(0x20, &[0xB8, 0x01, 0x00, 0xCB]);
+        // at 3:4, mov cx, 7; iret
+        place(0x34, &[0xB9, 0x07, 0x00, 0xCF]);
+        // the far pointer 3:4
+        place(0x40, &[0x04, 0x00, 0x03, 0x00]);
+        // at 4:0x10, jmp 6:0
+        place(0x50, &[0xEA, 0x00, 0x00, 0x06, 0x00]);
+        // at 6:0, mov dx, 5
+        place(0x60, &[0xBA, 0x05, 0x00]);
+
+        let mut state = State::default();
+        state.load_bytes(&program);
+        // the interrupt frame `iret` returns through, IP, CS and then the flags
+        state.memory[0x100..0x106].copy_from_slice(&[0x10, 0x00, 0x04, 0x00, 0x41, 0x08]);
+        exec::all_instructions(&mut state).unwrap();
+
+        let registers = &state.registers;
+        assert_eq!((registers[AX], registers[CX], registers[DX]), (1, 7, 5));
+        assert_eq!((registers[CS], state.ip()), (6, 3));
+        assert_eq!(state.instruction_pointer, 0x63);
+        assert_eq!(registers[SP], 0x106);
+        assert_eq!(registers.flags_string(), "CZO");
+        // the return address of the call, IP below CS
+        assert_eq!(&state.memory[0xFC..0x100], &[0x08, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn return_pops_arguments() {
+        // mov sp, 0x100; mov ss, sp; call 1:0; at 1:0, retf 4
+        let mut state = State::default();
+        state.load_bytes(&[
+            0xBC, 0x00, 0x01, 0x8E, 0xD4, 0x9A, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0xCA, 0x04, 0x00,
+        ]);
+        state.step();
+        state.step();
+        state.step();
+        assert_eq!((state.registers[CS], state.ip()), (1, 0));
+        // the stack lives in segment 0x100 now
+        assert_eq!(&state.memory[0x10FC..0x1100], &[0x0A, 0x00, 0x00, 0x00]);
+        state.step();
+        assert_eq!((state.registers[CS], state.ip()), (0, 0x0A));
+        assert_eq!(state.registers[SP], 0x104);
+    }
+
+    #[test]
+    fn runs_code_loaded_outside_of_the_program() {
+        // jmp 0x100:0, into mov ax, 0x1234 loaded at 0x1000
+        let mut state = State::default();
+        state.load_bytes(&[0xEA, 0x00, 0x00, 0x00, 0x01]);
+        state.load_at(0x1000, &[0xB8, 0x34, 0x12]).unwrap();
+        exec::all_instructions(&mut state).unwrap();
+        assert_eq!(state.registers[AX], 0x1234);
+        assert_eq!((state.registers[CS], state.ip()), (0x100, 3));
+    }
+
+    #[test]
+    fn segments_reach_past_the_first_64_kib() {
+        // jmp 0x1000:0, into mov ax, 0x1234; mov [0], ax loaded at 0x10000
+        let mut state = State::default();
+        state.load_bytes(&[0xEA, 0x00, 0x00, 0x00, 0x10]);
+        state
+            .load_at(0x10000, &[0xB8, 0x34, 0x12, 0xA3, 0x00, 0x00])
+            .unwrap();
+        state.registers[DS] = 0x1000;
+        exec::all_instructions(&mut state).unwrap();
+        assert_eq!(state.registers[AX], 0x1234);
+        assert_eq!((state.registers[CS], state.ip()), (0x1000, 6));
+        assert_eq!(state.instruction_pointer, 0x10006);
+        // the write lands at 0x10000, the program at address 0 stays as it was
+        assert_eq!(&state.memory[0x10000..0x10002], &[0x34, 0x12]);
+        assert_eq!(&state.memory[..2], &[0xEA, 0x00]);
+    }
+
+    #[test]
+    fn ip_wraps_around_the_code_segment() {
+        // the zero flag is clear, so every jnz is taken
+        // at 0x100:0xFFFC jnz $+6 to 0x100:2; at 0x100:0xFFFE mov al, 5, running on into 0x100:0;
+        // at 0x100:0 jnz $-4 to 0x100:0xFFFC; at 0x100:2 mov ah, 7
+        let mut state = State::default();
+        state.load_at(0x10FFC, &[0x75, 0x04, 0xB0, 0x05]).unwrap();
+        state.load_at(0x1000, &[0x75, 0xFA, 0xB4, 0x07]).unwrap();
+        state.jump_far(0x100, 0xFFFE);
+        exec::all_instructions(&mut state).unwrap();
+        assert_eq!(state.registers[AX], 0x0705);
+        assert_eq!((state.registers[CS], state.ip()), (0x100, 4));
+        assert_eq!(state.instruction_pointer, 0x1004);
+    }
+
+    #[test]
+    fn far_transfer_clocks() {
+        let clocks = |bytes: &[u8]| {
+            let (_, instruction) = decode::single_instruction(&mut &bytes[..]);
+            cycles::estimate(&instruction, true, 0)
+        };
+        // call 1:0; jmp 1:0; call far [bx]; jmp far [1000]
+        assert_eq!(clocks(&[0x9A, 0x00, 0x00, 0x01, 0x00]), Some(28));
+        assert_eq!(clocks(&[0xEA, 0x00, 0x00, 0x01, 0x00]), Some(15));
+        assert_eq!(clocks(&[0xFF, 0x1F]), Some(37 + 5));
+        assert_eq!(clocks(&[0xFF, 0x2E, 0xE8, 0x03]), Some(24 + 6));
+        // retf; retf 4; iret
+        assert_eq!(clocks(&[0xCB]), Some(18));
+        assert_eq!(clocks(&[0xCA, 0x04, 0x00]), Some(17));
+        assert_eq!(clocks(&[0xCF]), Some(24));
+    }
+}
+
+mod registers {
+    use crate::{exec::Registers, tests::run_program, RegisterByte, RegisterWord::*};
+
+    #[test]
+    fn byte_registers_alias_the_matching_halves() {
+        let words = [AX, CX, DX, BX];
+        for octal in 0..8u8 {
+            let register = RegisterByte::from_octal(octal).unwrap();
+            let (word, high) = (words[octal as usize & 0b11], octal >= 4);
+
+            let mut registers = Registers::default();
+            for (index, &other) in words.iter().enumerate() {
+                registers[other] = 0x1111 * (index as u16 + 1);
+            }
+            let before = registers[word];
+            registers.set_byte(register, 0xAB);
+
+            let expected = if high {
+                before & 0x00FF | 0xAB00
+            } else {
+                before & 0xFF00 | 0x00AB
+            };
+            assert_eq!(registers[word], expected, "{register:?}");
+            assert_eq!(registers.byte(register), 0xAB, "{register:?}");
+            for (index, &other) in words.iter().enumerate() {
+                if other != word {
+                    assert_eq!(
+                        registers[other],
+                        0x1111 * (index as u16 + 1),
+                        "{register:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn simulated_byte_moves() {
+        // mov ax, 0x1234; mov bl, 7; mov dh, 0x56
+        let state = run_program(&[0xB8, 0x34, 0x12, 0xB3, 0x07, 0xB6, 0x56]);
+        let registers = &state.registers;
+        assert_eq!(registers.byte(RegisterByte::AL), 0x34);
+        assert_eq!(registers.byte(RegisterByte::AH), 0x12);
+        assert_eq!(registers[BX], 7);
+        assert_eq!(registers[CX], 0);
+        assert_eq!(registers[DX], 0x5600);
+    }
+}
+
+mod typed {
+    use crate::{
+        decode, tests::run_program, AddressBase, ArithOp, Instruction, JumpCondition, LoopKind,
+        Place, RegisterWord::*, SegmentRegister::*, Width,
+    };
+
+    #[test]
+    fn decoded_instructions_can_be_matched() {
+        let (_, instruction) = decode::single_instruction(&mut &[0x83, 0xC6, 0xFE][..]);
+        assert_eq!(
+            instruction,
+            Instruction::ArithmeticImmediate {
+                op: ArithOp::Add,
+                width: Width::Word,
+                target: Place::Word(SI),
+                immediate: -2,
+            }
+        );
+
+        let (_, instruction) = decode::single_instruction(&mut &[0x7E, 0xFC][..]);
+        assert_eq!(
+            instruction,
+            Instruction::Jump {
+                condition: JumpCondition::LessOrEqual,
+                offset: -4,
+            }
+        );
+
+        let (_, instruction) = decode::single_instruction(&mut &[0xE3, 0x02][..]);
+        assert_eq!(
+            instruction,
+            Instruction::Loop {
+                kind: LoopKind::Jcxz,
+                offset: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn memory_operands_can_be_inspected() {
+        // mov cx, es:[bp + di - 2]
+        let (_, instruction) = decode::single_instruction(&mut &[0x26, 0x8B, 0x4B, 0xFE][..]);
+        let Instruction::Mov {
+            source: Place::Adress(address),
+            ..
+        } = instruction
+        else {
+            panic!("expected a move from memory, got `{instruction}`");
+        };
+        assert_eq!(address.base(), Some(AddressBase::BpDi));
+        assert_eq!(AddressBase::BpDi.registers(), (BP, Some(DI)));
+        assert_eq!(address.displacement(), -2);
+        assert_eq!(address.displacement_width(), Some(Width::Byte));
+        assert_eq!(address.segment(), Some(ES));
+        assert!(!address.is_direct());
+
+        // mov ax, [1000]
+        let (_, instruction) = decode::single_instruction(&mut &[0xA1, 0xE8, 0x03][..]);
+        let Instruction::Mov {
+            source: Place::Adress(address),
+            ..
+        } = instruction
+        else {
+            panic!("expected a move from memory, got `{instruction}`");
+        };
+        assert!(address.is_direct());
+        assert_eq!(address.base(), None);
+        assert_eq!(address.displacement(), 1000);
+        assert_eq!(address.displacement_width(), Some(Width::Word));
+        assert_eq!(address.segment(), None);
+    }
+
+    #[test]
+    fn loop_counts_down_cx() {
+        // mov cx, 3; mov ax, 0; add ax, 2; loop $-3
+        let state = run_program(&[
+            0xB9, 0x03, 0x00, 0xB8, 0x00, 0x00, 0x83, 0xC0, 0x02, 0xE2, 0xFB,
+        ]);
+        assert_eq!(state.registers[AX], 6);
+        assert_eq!(state.registers[CX], 0);
+    }
+
+    #[test]
+    fn signed_and_unsigned_conditions() {
+        // mov ax, -1; cmp ax, 1; jl $+5; mov bx, 1; jb $+5; mov cx, 1
+        let state = run_program(&[
+            0xB8, 0xFF, 0xFF, 0x83, 0xF8, 0x01, 0x7C, 0x03, 0xBB, 0x01, 0x00, 0x72, 0x03, 0xB9,
+            0x01, 0x00,
+        ]);
+        // -1 is less than 1, but 0xFFFF is not below 1
+        assert_eq!(state.registers[BX], 0);
+        assert_eq!(state.registers[CX], 1);
+    }
+}
+
+mod cfg {
+    use crate::cfg::{ControlFlowGraph, Edge, EdgeKind};
+
+    // mov cx, 3; mov ax, 0; add ax, 2; loop $-3; cmp ax, 6; jz $+4; mov bx, 1; mov dx, 1
+    const PROGRAM: [u8; 22] = [
+        0xB9, 0x03, 0x00, 0xB8, 0x00, 0x00, 0x83, 0xC0, 0x02, 0xE2, 0xFB, 0x83, 0xF8, 0x06, 0x74,
+        0x03, 0xBB, 0x01, 0x00, 0xBA, 0x01, 0x00,
+    ];
+
+    #[test]
+    fn splits_into_basic_blocks() {
+        let graph = ControlFlowGraph::from_code(&PROGRAM);
+        let bounds: Vec<_> = graph.blocks.iter().map(|b| (b.start, b.end)).collect();
+        assert_eq!(bounds, [(0, 6), (6, 11), (11, 16), (16, 19), (19, 22)]);
+    }
+
+    #[test]
+    fn connects_loops_and_jumps() {
+        let graph = ControlFlowGraph::from_code(&PROGRAM);
+        let edge = |from, to, kind| Edge { from, to, kind };
+        assert_eq!(
+            graph.edges,
+            [
+                edge(0, 1, EdgeKind::FallThrough),
+                edge(1, 1, EdgeKind::Taken),
+                edge(1, 2, EdgeKind::FallThrough),
+                edge(2, 4, EdgeKind::Taken),
+                edge(2, 3, EdgeKind::FallThrough),
+                edge(3, 4, EdgeKind::FallThrough),
+            ]
+        );
+    }
+
+    #[test]
+    fn follows_calls_and_stops_after_jumps_and_returns() {
+        // call $+4; hlt; mov ax, 1; jmp $+3; hlt; ret
+        let program = [
+            0xE8, 0x01, 0x00, 0xF4, 0xB8, 0x01, 0x00, 0xEB, 0x01, 0xF4, 0xC3,
+        ];
+        let graph = ControlFlowGraph::from_code(&program);
+        let bounds: Vec<_> = graph.blocks.iter().map(|b| (b.start, b.end)).collect();
+        assert_eq!(bounds, [(0, 3), (3, 4), (4, 9), (9, 10), (10, 11)]);
+        let edge = |from, to, kind| Edge { from, to, kind };
+        assert_eq!(
+            graph.edges,
+            [
+                edge(0, 2, EdgeKind::Call),
+                edge(0, 1, EdgeKind::FallThrough),
+                edge(2, 4, EdgeKind::Taken),
+            ]
+        );
+    }
+
+    #[test]
+    fn exports_dot() {
+        let dot = ControlFlowGraph::from_code(&PROGRAM).to_dot();
+        assert!(dot.starts_with("digraph cfg {"));
+        assert!(dot.contains("b1 [label=\"0006: add AX, 2\\l0009: loop $+2-5\\l\"];"));
+        assert!(dot.contains("b1 -> b1 [label=\"taken\"];"));
+        assert!(dot.contains("b3 -> b4 [style=dashed];"));
+    }
+}
+
+mod fuzzing {
+    use super::assemble;
+    use crate::{decode, Instruction};
+
+    /// Small xorshift generator, so the random streams are reproducible from their seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: u64) -> u8 {
+            (self.next() % bound) as u8
+        }
+
+        fn word(&mut self) -> u16 {
+            self.next() as u16
+        }
+    }
+
+    /// Appends a mod r/m byte around `reg` with the displacement it calls for, choosing only the
+    /// encodings nasm itself would pick, so that reassembling gives back the same bytes.
+    fn push_mod_rm(rng: &mut Rng, bytes: &mut Vec<u8>, reg: u8, memory_only: bool) -> (u8, u8) {
+        let mode = if memory_only {
+            rng.below(3)
+        } else {
+            rng.below(4)
+        };
+        let r_m = rng.below(8);
+        bytes.push(mode << 6 | reg << 3 | r_m);
+        match mode {
+            0b00 if r_m == 0b110 => bytes.extend(rng.word().to_le_bytes()),
+            // `[bp]` has no encoding without a displacement, elsewhere nasm drops a zero one
+            0b01 if r_m == 0b110 => bytes.push(rng.below(256)),
+            0b01 => bytes.push(1 + rng.below(255)),
+            // and shrinks anything fitting into a signed byte
+            0b10 => {
+                let displacement = match rng.word() {
+                    small if (-128..=127).contains(&(small as i16)) => small ^ 0x8000,
+                    displacement => displacement,
+                };
+                bytes.extend(displacement.to_le_bytes());
+            }
+            _ => (),
+        }
+        (mode, r_m)
+    }
+
+    fn push_immediate(rng: &mut Rng, bytes: &mut Vec<u8>, word: bool) -> u16 {
+        let immediate = rng.word();
+        if word {
+            bytes.extend(immediate.to_le_bytes());
+        } else {
+            bytes.push(immediate as u8);
+        }
+        immediate
+    }
+
+    /// Appends one random instruction in its canonical encoding.
+    fn push_instruction(rng: &mut Rng, bytes: &mut Vec<u8>) {
+        let w = rng.below(2);
+        let reg = rng.below(8);
+        let op = rng.below(8);
+        match rng.below(11) {
+            // arithmetic, register to either
+            0 => {
+                let start = bytes.len();
+                bytes.push(op << 3 | w);
+                if push_mod_rm(rng, bytes, reg, false).0 != 0b11 {
+                    bytes[start] |= rng.below(2) << 1;
+                }
+            }
+            // arithmetic, immediate to register/memory
+            1 => {
+                let start = bytes.len();
+                bytes.push(0x80 | w);
+                let (mode, r_m) = push_mod_rm(rng, bytes, op, false);
+                if mode == 0b11 && r_m == 0 {
+                    // the accumulator has a shorter form of its own
+                    bytes.truncate(start);
+                    bytes.push(op << 3 | 0b100 | w);
+                    let immediate = push_immediate(rng, bytes, w == 1);
+                    if w == 1 && (immediate as i16).unsigned_abs() < 128 {
+                        bytes.truncate(start);
+                    }
+                } else if w == 1 && rng.below(2) == 0 {
+                    bytes[start] |= 0b10;
+                    bytes.push(rng.below(256));
+                } else {
+                    let immediate = push_immediate(rng, bytes, w == 1);
+                    if w == 1 && (immediate as i16).unsigned_abs() < 128 {
+                        bytes.truncate(start);
+                    }
+                }
+            }
+            // mov, register to either
+            2 => {
+                let start = bytes.len();
+                bytes.push(0x88 | w);
+                let (mode, r_m) = push_mod_rm(rng, bytes, reg, false);
+                if mode == 0b00 && r_m == 0b110 && reg == 0 {
+                    // the accumulator has a direct address form of its own
+                    bytes.truncate(start);
+                } else if mode != 0b11 {
+                    bytes[start] |= rng.below(2) << 1;
+                }
+            }
+            // mov, immediate to register
+            3 => {
+                bytes.push(0xB0 | w << 3 | reg);
+                push_immediate(rng, bytes, w == 1);
+            }
+            // mov, immediate to memory
+            4 => {
+                bytes.push(0xC6 | w);
+                push_mod_rm(rng, bytes, 0, true);
+                push_immediate(rng, bytes, w == 1);
+            }
+            // test, register and register/memory
+            5 => {
+                bytes.push(0x84 | w);
+                push_mod_rm(rng, bytes, reg, false);
+            }
+            // test, immediate and register/memory
+            6 => {
+                let start = bytes.len();
+                bytes.push(0xF6 | w);
+                let (mode, r_m) = push_mod_rm(rng, bytes, 0, false);
+                if mode == 0b11 && r_m == 0 {
+                    bytes.truncate(start);
+                    bytes.push(0xA8 | w);
+                }
+                push_immediate(rng, bytes, w == 1);
+            }
+            // not, neg
+            7 => {
+                bytes.push(0xF6 | w);
+                let op = 2 + rng.below(2);
+                push_mod_rm(rng, bytes, op, false);
+            }
+            // shifts and rotates, leaving out the undocumented sal
+            8 => {
+                bytes.push(0xD0 | rng.below(2) << 1 | w);
+                let op = if op == 0b110 { 0b100 } else { op };
+                push_mod_rm(rng, bytes, op, false);
+            }
+            // jumps and loops, never targeting anything before the start of the program
+            kind => {
+                let opcode = if kind == 9 {
+                    0x70 | rng.below(16)
+                } else {
+                    0xE0 | rng.below(4)
+                };
+                let next = (bytes.len() + 2) as i64;
+                let offset = (rng.below(256) as i8 as i64).max(-next);
+                bytes.extend([opcode, offset as u8]);
+            }
+        }
+    }
+
+    fn random_program(seed: u64, instructions: usize) -> Vec<u8> {
+        let mut rng = Rng(seed);
+        let mut bytes = Vec::new();
+        for _ in 0..instructions {
+            push_instruction(&mut rng, &mut bytes);
+        }
+        bytes
+    }
+
+    #[test]
+    fn random_bytes_never_panic() {
+        let mut rng = Rng(0x8086);
+        for _ in 0..10_000 {
+            let length = rng.below(16) as usize;
+            let bytes: Vec<u8> = (0..length).map(|_| rng.below(256)).collect();
+            let mut memory = &bytes[..];
+            let mut decoded = 0;
+            while !memory.is_empty() {
+                let (offset, instruction) = decode::single_instruction(&mut memory);
+                // a segment override prefix on top of the longest instructions makes 7
+                assert!(
+                    (1..=7).contains(&offset),
+                    "{bytes:02x?} decoded {offset} bytes"
+                );
+                let _ = instruction.to_string();
+                decoded += offset;
+            }
+            assert_eq!(decoded, length);
+        }
+    }
+
+    #[test]
+    fn truncated_instructions_are_unrecognized() {
+        let mut memory = &[0x81, 0x06, 0x34][..];
+        let (offset, instruction) = decode::single_instruction(&mut memory);
+        assert_eq!(offset, 1);
+        assert_eq!(instruction, Instruction::Unrecognized(0x81));
+        assert_eq!(memory, [0x06, 0x34]);
+    }
+
+    #[test]
+    fn runs_of_prefixes_are_unrecognized() {
+        // only the last prefix applies to the instruction after the run, `mov ax, es:[bx]` and
+        // `rep movsb`
+        for (prefix, instruction) in [(0x26, &[0x8B, 0x07][..]), (0xF3, &[0xA4])] {
+            let run = 100_000;
+            let mut bytes = vec![prefix; run];
+            bytes.extend(instruction);
+            let mut memory = &bytes[..];
+            for _ in 1..run {
+                let (offset, decoded) = decode::single_instruction(&mut memory);
+                assert_eq!((offset, decoded), (1, Instruction::Unrecognized(prefix)));
+            }
+            let (offset, decoded) = decode::single_instruction(&mut memory);
+            assert_eq!(offset, 1 + instruction.len(), "{decoded}");
+            assert!(memory.is_empty());
+        }
+    }
+
+    #[test]
+    fn random_programs_round_trip() {
+        for seed in 1..=8 {
+            let program = random_program(seed * 0x9E37_79B9, 256);
+            let disassembly = decode::all_instructions(&program);
+            let (assembled, _) = assemble(disassembly, &format!("random_program_{seed}"));
+
+            // point at the first instruction that came back different
+            let mut memory = &program[..];
+            while !memory.is_empty() && program != assembled {
+                let address = program.len() - memory.len();
+                let (length, instruction) = decode::single_instruction(&mut memory);
+                let range = address..address + length;
+                assert_eq!(
+                    program.get(range.clone()),
+                    assembled.get(range),
+                    "seed {seed}, address {address}: `{instruction}` was reassembled differently"
+                );
+            }
+            assert_eq!(program, assembled);
+        }
+    }
+}
+
+mod statistics {
+    use crate::{
+        exec::State,
+        stats::{InstructionMix, Statistics, Tally},
+    };
+
+    // mov cx, 3; mov word [bx + 1], 0; add word [bx + 1], 2; loop $-5
+    const PROGRAM: [u8; 14] = [
+        0xB9, 0x03, 0x00, 0xC7, 0x47, 0x01, 0x00, 0x00, 0x83, 0x47, 0x01, 0x02, 0xE2, 0xFA,
+    ];
+
+    fn run_with_statistics(count_cycles: bool) -> Statistics {
+        let mut state = State::default();
+        state.load_bytes(&PROGRAM);
+        state.statistics = Some(Statistics::new(count_cycles));
+        crate::exec::all_instructions(&mut state).unwrap();
+        assert_eq!(&state.memory[1..3], &[6, 0]);
+        state.statistics.unwrap()
+    }
+
+    #[test]
+    fn counts_executions_and_memory_accesses() {
+        let statistics = run_with_statistics(false);
+        assert_eq!(statistics.instructions(), 8);
+        assert_eq!(statistics.mnemonics["add"], 3);
+        assert_eq!(statistics.mnemonics["loop"], 3);
+        assert_eq!(statistics.addresses[&8].executions, 3);
+        assert_eq!(statistics.memory_reads, 3);
+        assert_eq!(statistics.memory_writes, 4);
+        assert_eq!(statistics.branches_taken, 2);
+        assert_eq!(statistics.branches_not_taken, 1);
+        assert_eq!(statistics.cycles, 0);
+    }
+
+    #[test]
+    fn estimates_cycles_per_address() {
+        let statistics = run_with_statistics(true);
+        // mov cx, imm
+        assert_eq!(statistics.addresses[&0].cycles, 4);
+        // mov word [bx + 1], imm: 10 + 9ea + 4 for the odd address
+        assert_eq!(statistics.addresses[&3].cycles, 23);
+        // add word [bx + 1], imm three times: 17 + 9ea + 2 * 4 for the odd address
+        assert_eq!(statistics.addresses[&8].cycles, 3 * 34);
+        // loop taken twice, then falling through
+        assert_eq!(statistics.addresses[&12].cycles, 17 + 17 + 5);
+        assert_eq!(statistics.cycles, 4 + 23 + 3 * 34 + 39);
+
+        let report = statistics.report();
+        assert!(report.contains("Cycles: 168"));
+        assert!(report.contains("  0008        3      102  add word [BX + 1], 2"));
+    }
+
+    #[test]
+    fn static_instruction_mix() {
+        // the loop counts once, however often it would run; mov [1000], al; in al, dx; ret
+        let mut code = PROGRAM.to_vec();
+        code.extend([0xA2, 0xE8, 0x03, 0xEC, 0xC3]);
+        let mix = InstructionMix::from_code(&code);
+        let tally = |count, bytes| Tally { count, bytes };
+
+        assert_eq!((mix.instructions(), mix.bytes()), (7, 19));
+        assert_eq!(mix.mnemonics["mov"], tally(3, 11));
+        assert_eq!(mix.mnemonics["loop"], tally(1, 2));
+        assert_eq!(mix.addressing_modes["immediate"], tally(1, 3));
+        assert_eq!(mix.addressing_modes["indirect+disp"], tally(2, 9));
+        assert_eq!(mix.addressing_modes["direct"], tally(1, 3));
+        assert_eq!(mix.addressing_modes["register"], tally(1, 1));
+        assert_eq!(mix.addressing_modes["relative"], tally(1, 2));
+        assert_eq!(mix.addressing_modes["implied"], tally(1, 1));
+        assert_eq!(mix.widths["word"], tally(3, 12));
+        assert_eq!(mix.widths["byte"], tally(2, 4));
+        assert_eq!(mix.widths["none"], tally(2, 3));
+
+        let report = mix.report();
+        assert!(
+            report.starts_with("Instructions: 7, bytes: 19\n"),
+            "{report}"
+        );
+        assert!(
+            report.contains("\n  mov                       3      11\n"),
+            "{report}"
+        );
+    }
+}
+
+mod prefetch {
+    use crate::{
+        exec::{self, State},
+        prefetch::{BusUnit, Cpu},
+        stats::Statistics,
+    };
+
+    fn run_on_bus(program: &[u8], cpu: Cpu, wait_states: u32) -> State {
+        let mut state = State::default();
+        state.load_bytes(program);
+        state.statistics = Some(Statistics::new(true));
+        state.bus = Some(BusUnit::new(cpu, wait_states, 0));
+        exec::all_instructions(&mut state).unwrap();
+        state
+    }
+
+    #[test]
+    fn short_instructions_outrun_the_8088_bus() {
+        // eight times inc ax, 2 clocks each by the tables
+        let program = [0x40; 8];
+        let state = run_on_bus(&program, Cpu::I8086, 0);
+        let bus = state.bus.unwrap();
+        // only waits for the very first fetch, the 8086 fetches a word every 4 clocks
+        assert_eq!((bus.clock(), bus.stalled), (4 + 8 * 2, 4));
+        assert_eq!(state.statistics.unwrap().cycles, bus.clock());
+
+        // the 8088 fetches a single byte every 4 clocks and keeps the execution unit waiting
+        let bus = run_on_bus(&program, Cpu::I8088, 0).bus.unwrap();
+        assert_eq!((bus.clock(), bus.stalled), (32, 16));
+        assert_eq!(bus.fetches, 8);
+    }
+
+    #[test]
+    fn taken_jumps_flush_the_queue() {
+        // mov cx, 3; loop $+0
+        let program = [0xB9, 0x03, 0x00, 0xE2, 0xFE];
+        let state = run_on_bus(&program, Cpu::I8086, 0);
+        let bus = state.bus.as_ref().unwrap();
+        assert_eq!(bus.flushes, 2);
+        // every taken loop has to wait for its own bytes to be fetched again
+        let loops = state.statistics.as_ref().unwrap().addresses[&3].cycles;
+        assert!(loops > 17 + 17 + 5, "{loops}");
+        assert_eq!(bus.stalled, bus.clock() - (4 + 17 + 17 + 5));
+    }
+
+    #[test]
+    fn wait_states_slow_down_every_bus_cycle() {
+        // mov bx, 1; add word [bx], 2: the unaligned word takes two reads and two writes
+        let program = [0xBB, 0x01, 0x00, 0x83, 0x07, 0x02];
+        let fast = run_on_bus(&program, Cpu::I8086, 0).bus.unwrap();
+        let slow = run_on_bus(&program, Cpu::I8086, 3).bus.unwrap();
+        assert!(
+            slow.clock() >= fast.clock() + 4 * 3,
+            "{} {}",
+            slow.clock(),
+            fast.clock()
+        );
+
+        let report = slow.report();
+        assert!(report.starts_with("Bus: 8086, 6 byte queue, 3 wait states\n"));
+    }
+
+    #[test]
+    fn every_word_takes_two_bus_cycles_on_the_8088() {
+        // mov bx, 2; add word [bx], 2: 4 and 17 + 5ea by the tables, with the 8088 taking another
+        // 4 for each of the words
+        let program = [0xBB, 0x02, 0x00, 0x83, 0x07, 0x02];
+        let executing = |cpu| {
+            let bus = run_on_bus(&program, cpu, 0).bus.unwrap();
+            bus.clock() - bus.stalled
+        };
+        assert_eq!(executing(Cpu::I8086), 4 + 22);
+        assert_eq!(executing(Cpu::I8088), 4 + 22 + 2 * 4);
+    }
+}
+
+mod encoding {
+    use crate::{
+        decode,
+        encode::{encodings, Encoding, Form},
+        Instruction,
+    };
+
+    fn forms(bytes: &[u8]) -> Vec<(Form, Vec<u8>)> {
+        let (length, instruction) = decode::single_instruction(&mut &bytes[..]);
+        assert_eq!(length, bytes.len(), "{instruction}");
+        encodings(&instruction)
+            .into_iter()
+            .map(|Encoding { form, bytes }| (form, bytes))
+            .collect()
+    }
+
+    #[test]
+    fn alternative_encodings() {
+        // add BX, CX
+        assert_eq!(
+            forms(&[0x01, 0xCB]),
+            [
+                (Form::General, vec![0x01, 0xCB]),
+                (Form::Direction, vec![0x03, 0xD9])
+            ]
+        );
+        // add AX, 5
+        assert_eq!(
+            forms(&[0x05, 0x05, 0x00]),
+            [
+                (Form::General, vec![0x81, 0xC0, 0x05, 0x00]),
+                (Form::SignExtended, vec![0x83, 0xC0, 0x05]),
+                (Form::Accumulator, vec![0x05, 0x05, 0x00]),
+            ]
+        );
+        // mov AL, ES:[1000]
+        assert_eq!(
+            forms(&[0x26, 0xA0, 0xE8, 0x03]),
+            [
+                (Form::Direction, vec![0x26, 0x8A, 0x06, 0xE8, 0x03]),
+                (Form::Accumulator, vec![0x26, 0xA0, 0xE8, 0x03]),
+            ]
+        );
+        // push [BP - 2]
+        assert_eq!(
+            forms(&[0xFF, 0x76, 0xFE]),
+            [
+                (Form::General, vec![0xFF, 0x76, 0xFE]),
+                (Form::General, vec![0xFF, 0xB6, 0xFE, 0xFF]),
+            ]
+        );
+        // xchg AX, SI
+        assert_eq!(
+            forms(&[0x96]),
+            [
+                (Form::General, vec![0x87, 0xC6]),
+                (Form::ShortRegister, vec![0x96]),
+            ]
+        );
+        // a test with a memory source can't be encoded, the r/m operand always comes first
+        let (_, test) = decode::single_instruction(&mut &[0x85, 0x07][..]);
+        let Instruction::Test { target, source } = test else {
+            panic!("{test}");
+        };
+        assert!(encodings(&Instruction::Test {
+            target: source,
+            source: target
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn every_displacement_width() {
+        let encoded = |bytes: &[u8]| -> Vec<Vec<u8>> {
+            let (_, instruction) = decode::single_instruction(&mut &bytes[..]);
+            let encodings = encodings(&instruction);
+            for Encoding { bytes, .. } in &encodings {
+                let decoded = decode::single_instruction(&mut &bytes[..]);
+                assert_eq!(decoded, (bytes.len(), instruction), "{bytes:02x?}");
+            }
+            encodings
+                .into_iter()
+                .map(|encoding| encoding.bytes)
+                .collect()
+        };
+        // mov AX, [BX], without a displacement, with a byte or a word of zero
+        assert_eq!(
+            encoded(&[0x8B, 0x07]),
+            [
+                vec![0x8B, 0x07],
+                vec![0x8B, 0x47, 0x00],
+                vec![0x8B, 0x87, 0x00, 0x00]
+            ]
+        );
+        // mov AX, [BP], which always needs a displacement
+        assert_eq!(
+            encoded(&[0x8B, 0x86, 0x00, 0x00]),
+            [vec![0x8B, 0x46, 0x00], vec![0x8B, 0x86, 0x00, 0x00]]
+        );
+        // mov AX, [BX - 2], with the byte sign extended
+        assert_eq!(
+            encoded(&[0x8B, 0x87, 0xFE, 0xFF]),
+            [vec![0x8B, 0x47, 0xFE], vec![0x8B, 0x87, 0xFE, 0xFF]]
+        );
+        // mov AX, [BX + 300] only fits a word
+        assert_eq!(
+            encoded(&[0x8B, 0x87, 0x2C, 0x01]),
+            [vec![0x8B, 0x87, 0x2C, 0x01]]
+        );
+    }
+
+    /// Every decodable instruction is among its own encodings, all of which decode back to it.
+    #[test]
+    fn decoder_accepts_every_encoding() {
+        const TAILS: [[u8; 6]; 5] = [
+            [0xC1, 0x05, 0x00, 0x02, 0x00, 0x03],
+            [0x46, 0xFE, 0x34, 0x12, 0x78, 0x56],
+            [0x87, 0x34, 0x12, 0x80, 0xFF, 0x00],
+            [0x3E, 0x10, 0x00, 0x7F, 0x01, 0x02],
+            [0x0A, 0xD8, 0x9C, 0x00, 0x00, 0x00],
+        ];
+        for first in 0..=u8::MAX {
+            for second in 0..=u8::MAX {
+                for tail in &TAILS {
+                    let bytes = [&[first, second][..], tail].concat();
+                    let (length, instruction) = decode::single_instruction(&mut &bytes[..]);
+                    if let Instruction::Unrecognized(_) = instruction {
+                        continue;
+                    }
+
+                    let encodings = encodings(&instruction);
+                    assert!(
+                        encodings.iter().any(|e| e.bytes == bytes[..length]),
+                        "{instruction} is missing {:02x?}",
+                        &bytes[..length]
+                    );
+                    for Encoding { bytes, .. } in encodings {
+                        let decoded = decode::single_instruction(&mut &bytes[..]);
+                        assert_eq!(decoded, (bytes.len(), instruction), "{bytes:02x?}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+mod ports {
+    use std::{cell::RefCell, io::Cursor, rc::Rc};
+
+    use crate::{
+        decode,
+        exec::{self, State},
+        ports::{Console, Pit, PortDevice},
+        RegisterByte::*,
+        RegisterWord::*,
+        Width,
+    };
+
+    /// Device answering every read with its port number, recording every write.
+    struct FakeDevice(Rc<RefCell<Vec<(u16, Width, u16)>>>);
+
+    impl PortDevice for FakeDevice {
+        fn read(&mut self, port: u16, _width: Width) -> u16 {
+            port
+        }
+
+        fn write(&mut self, port: u16, width: Width, value: u16) {
+            self.0.borrow_mut().push((port, width, value));
+        }
+    }
+
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn decode_in_and_out() {
+        let disassembly = decode::all_instructions(&[0xE4, 0x60, 0xED, 0xE6, 0x43, 0xEF]);
+        assert_eq!(
+            disassembly,
+            "bits 16\nin AL, 96\nin AX, DX\nout 67, AL\nout DX, AX\n"
+        );
+    }
+
+    #[test]
+    fn in_and_out_reach_devices() {
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        let mut state = State::default();
+        state.attach_device(0x60..=0x6F, FakeDevice(writes.clone()));
+        // in al, 0x61; mov dx, 0x0160; in ax, dx; mov bx, ax; out 0x62, al; mov ax, 0x1234; out dx, ax
+        state.load_bytes(&[
+            0xE4, 0x61, 0xBA, 0x60, 0x01, 0xED, 0x89, 0xC3, 0xE6, 0x62, 0xB8, 0x34, 0x12, 0xEF,
+        ]);
+        exec::all_instructions(&mut state).unwrap();
+
+        // nothing is attached to 0x160, so the bus floats high
+        assert_eq!(state.registers[BX], 0xFFFF);
+        assert_eq!(
+            *writes.borrow(),
+            [(0x62, Width::Byte, 0xFF)],
+            "the word out to DX went nowhere"
+        );
+
+        let mut state = State::default();
+        state.attach_device(0x60..=0x6F, FakeDevice(writes.clone()));
+        // in al, 0x61
+        state.load_bytes(&[0xE4, 0x61]);
+        exec::all_instructions(&mut state).unwrap();
+        assert_eq!(state.registers.byte(AL), 0x61);
+    }
+
+    #[test]
+    fn console_echoes_input() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut state = State::default();
+        state.attach_device(
+            0xE9..=0xE9,
+            Console {
+                input: Cursor::new(b"hi".to_vec()),
+                output: SharedOutput(output.clone()),
+            },
+        );
+        // in al, 0xE9; out 0xE9, al; in al, 0xE9; out 0xE9, al
+        state.load_bytes(&[0xE4, 0xE9, 0xE6, 0xE9, 0xE4, 0xE9, 0xE6, 0xE9]);
+        exec::all_instructions(&mut state).unwrap();
+        assert_eq!(*output.borrow(), b"hi");
+    }
+
+    #[test]
+    fn pit_counts_down_and_latches() {
+        let mut pit = Pit::default();
+        // channel 0, low then high byte, reload 0x1000
+        pit.write(0x43, Width::Byte, 0b0011_0100);
+        pit.write(0x40, Width::Byte, 0x00);
+        pit.write(0x40, Width::Byte, 0x10);
+        assert_eq!(pit.count(0), 0x1000);
+
+        // four CPU clocks per PIT tick
+        pit.tick(402);
+        pit.write(0x43, Width::Byte, 0b0000_0000);
+        pit.tick(400);
+        let low = pit.read(0x40, Width::Byte);
+        let high = pit.read(0x40, Width::Byte);
+        assert_eq!(high << 8 | low, 0x1000 - 100);
+        assert_eq!(pit.count(0), 0x1000 - 200);
+
+        // wraps around to the reload value
+        pit.tick(4 * 0x1000);
+        assert_eq!(pit.count(0), 0x1000 - 200);
+    }
+
+    #[test]
+    fn pit_ticks_with_executed_instructions() {
+        let mut state = State::default();
+        state.attach_device(Pit::PORTS, Pit::default());
+        // mov al, 0x34; out 0x43, al; mov al, 0; out 0x40, al; out 0x40, al; mov cx, 100; loop $-2
+        // mov al, 0; out 0x43, al; in al, 0x40; mov ah, al; in al, 0x40
+        state.load_bytes(&[
+            0xB0, 0x34, 0xE6, 0x43, 0xB0, 0x00, 0xE6, 0x40, 0xE6, 0x40, 0xB9, 0x64, 0x00, 0xE2,
+            0xFE, 0xB0, 0x00, 0xE6, 0x43, 0xE4, 0x40, 0x88, 0xC4, 0xE4, 0x40,
+        ]);
+        exec::all_instructions(&mut state).unwrap();
+        let count = (state.registers.byte(AL) as u16) << 8 | state.registers.byte(AH) as u16;
+        // the 100 loops alone take well over 1600 clocks, a quarter of which the timer counted
+        assert!(
+            (0x10000 - 500..0x10000 - 400).contains(&(count as u32)),
+            "{count:#x}"
+        );
+    }
+}
+
+mod watchpoints {
+    use crate::{
+        exec::{self, State, StopReason},
+        watch::{AccessKind, MemoryAccess, WatchAction, Watchpoint},
+        Width,
+    };
+
+    // mov cx, 3; mov word [bx + 1], 0; add word [bx + 1], 2; loop $-5; mov byte [0x100], 7
+    const PROGRAM: [u8; 19] = [
+        0xB9, 0x03, 0x00, 0xC7, 0x47, 0x01, 0x00, 0x00, 0x83, 0x47, 0x01, 0x02, 0xE2, 0xFA, 0xC6,
+        0x06, 0x00, 0x01, 0x07,
+    ];
+
+    fn watching(range: std::ops::RangeInclusive<usize>, reads: bool, action: WatchAction) -> State {
+        let mut state = State::default();
+        state.load_bytes(&PROGRAM);
+        state.add_watchpoint(Watchpoint {
+            range,
+            reads,
+            writes: true,
+            action,
+        });
+        state
+    }
+
+    #[test]
+    fn logs_reads_and_writes() {
+        let mut state = watching(2..=2, true, WatchAction::Log);
+        exec::all_instructions(&mut state).unwrap();
+
+        let log = &state.watchpoints.log;
+        assert_eq!(log.len(), 7, "{log:#?}");
+        assert_eq!(
+            log[0],
+            MemoryAccess {
+                instruction: 3,
+                kind: AccessKind::Write,
+                address: 1,
+                width: Width::Word,
+                old: 0x0003,
+                new: 0
+            },
+            "the word overlaps the watched byte"
+        );
+        assert_eq!(log[5].kind, AccessKind::Read);
+        assert_eq!((log[6].old, log[6].new), (4, 6));
+        assert!(log[1..].iter().all(|access| access.instruction == 8));
+    }
+
+    #[test]
+    fn ignores_reads_and_other_addresses() {
+        let mut state = watching(0x100..=0x100, false, WatchAction::Log);
+        exec::all_instructions(&mut state).unwrap();
+        assert_eq!(
+            state.watchpoints.log,
+            [MemoryAccess {
+                instruction: 14,
+                kind: AccessKind::Write,
+                address: 0x100,
+                width: Width::Byte,
+                old: 0,
+                new: 7
+            }]
+        );
+    }
+
+    #[test]
+    fn breaks_after_the_access() {
+        let mut state = watching(1..=2, false, WatchAction::Break);
+        let result = exec::all_instructions(&mut state);
+        let Err(StopReason::Watchpoint(access)) = result else {
+            panic!("expected a watchpoint stop, got {result:?}");
+        };
+        assert_eq!((access.instruction, access.new), (3, 0));
+        assert_eq!(state.instruction_pointer, 8);
+
+        // resuming stops again at the next write
+        state.stop = None;
+        let result = exec::all_instructions(&mut state);
+        assert!(matches!(
+            result,
+            Err(StopReason::Watchpoint(MemoryAccess { new: 2, .. }))
+        ));
+        assert_eq!(state.watchpoints.log.len(), 2);
+    }
+}
+
+mod listing {
+    use crate::listing::{SourceLine, SourceMap};
+
+    const LISTING: &str = "\
+     1                                  bits 16
+     2                                  
+     3                                  start:
+     4 00000000 B90300                      mov cx, 3 ; counter
+     5 00000003 B03B                        mov al, ';'
+     6 00000005 E80600                  .again: call print
+     7 00000008 E2FB                        loop .again
+     8 0000000A 010203040506070809-     table: db 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11
+     8 00000013 0A0B               
+     9 00000015 <res 00000002>          scratch: resb 2
+    10                                  %macro out 0
+    11                                      out 0xE9, al
+    12                                  %endmacro
+    13                                  print:
+    14                              <1>  out 0xE9, al
+    14 00000017 E6E9                <1>  out 0xE9, al
+    15 00000019 C3                          ret
+";
+
+    #[test]
+    fn maps_addresses_to_lines_and_labels() {
+        let source = SourceMap::parse(LISTING).unwrap();
+        let locate = |address| {
+            source
+                .locate(address)
+                .map(|location| (location.line.clone(), location.to_string()))
+        };
+        let line = |number, text: &str| SourceLine {
+            number,
+            text: text.to_owned(),
+        };
+
+        assert_eq!(
+            locate(0),
+            Some((line(4, "mov cx, 3"), "line 4, start".into()))
+        );
+        assert_eq!(
+            locate(3),
+            Some((line(5, "mov al, ';'"), "line 5, start+3".into()))
+        );
+        assert_eq!(
+            locate(5),
+            Some((line(6, "call print"), "line 6, start.again".into()))
+        );
+        assert_eq!(
+            locate(8),
+            Some((line(7, "loop .again"), "line 7, start.again+3".into()))
+        );
+        assert_eq!(locate(0x0A).unwrap().1, "line 8, table");
+        // continued data and the middle of instructions have no line of their own
+        assert_eq!(locate(0x13), None);
+        assert_eq!(locate(1), None);
+        assert_eq!(locate(0x15).unwrap().1, "line 9, scratch");
+        assert_eq!(
+            locate(0x17),
+            Some((line(14, "out 0xE9, al"), "line 14, print".into()))
+        );
+        assert_eq!(locate(0x19).unwrap().1, "line 15, print+2");
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(SourceMap::parse("bits 16\nmov ax, bx\n").is_err());
+        assert!(SourceMap::parse("").unwrap().is_empty());
+    }
+}
+
+mod setup {
+    use std::path::Path;
+
+    use crate::{
+        exec::{self, State},
+        setup::{Data, Load, Setup},
+        RegisterByte::*,
+        RegisterWord::*,
+        SegmentRegister::*,
+    };
+
+    #[test]
+    fn prepares_registers_and_memory() {
+        let setup = Setup::parse(
+            "# sums the bytes of the buffer at SI
+            [registers]
+            si = 0x100   # the buffer
+            CX = 4
+            bh = -1
+            ds = 0b10
+            ip = 3
+            flags = \"CO\"
+
+            [[load]]
+            address = 0x100
+            bytes = [1, 2, 3, 250,]
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            setup.loads,
+            [Load {
+                address: 0x100,
+                data: Data::Bytes(vec![1, 2, 3, 250])
+            }]
+        );
+
+        // jmp $; add al, [si]; inc si; loop -5
+        let mut state = State::default();
+        state.load_bytes(&[0xEB, 0xFE, 0x90, 0x02, 0x04, 0x46, 0xE2, 0xFB]);
+        setup.apply(&mut state, Path::new("")).unwrap();
+        assert_eq!(state.registers.flags_string(), "CO");
+        assert_eq!(state.registers[BX], 0xFF00);
+        assert_eq!(state.registers[DS], 2);
+
+        // starting past the endless jump and the nop, which the simulator can't execute
+        assert_eq!(state.instruction_pointer, 3);
+        exec::all_instructions(&mut state).unwrap();
+        assert_eq!(state.registers.byte(AL), 0);
+        assert_eq!(state.registers[SI], 0x104);
+        assert_eq!(&state.memory[0x100..0x104], &[1, 2, 3, 250]);
+    }
+
+    #[test]
+    fn loads_files_relative_to_the_setup() {
+        let directory = std::env::temp_dir().join(format!("sim86-setup-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("input.bin"), b"8086").unwrap();
+
+        let setup = Setup::parse("[[load]]\naddress = 0xFFFFC\nfile = \"input.bin\"").unwrap();
+        let mut state = State::default();
+        setup.apply(&mut state, &directory).unwrap();
+        assert_eq!(&state.memory[0xFFFFC..], b"8086");
+
+        // one byte further would not fit into memory
+        let setup = Setup::parse("[[load]]\naddress = 0xFFFFD\nfile = \"input.bin\"").unwrap();
+        assert!(setup.apply(&mut state, &directory).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn reports_mistakes() {
+        let error = |text| Setup::parse(text).unwrap_err();
+        assert_eq!(error("ax = 1"), "line 1: `ax` outside of a table");
+        assert_eq!(
+            error("[registers]\nxx = 1"),
+            "line 2: unknown register `xx`"
+        );
+        assert_eq!(
+            error("[registers]\nal = 256"),
+            "line 2: 256 does not fit into `al`"
+        );
+        assert_eq!(
+            error("[registers]\nflags = \"CX\""),
+            "line 2: unknown flag `X`, expected some of CPAZSO"
+        );
+        assert_eq!(
+            error("[[load]]\nbytes = [1]\n[registers]"),
+            "line 3: `[[load]]` without an `address`"
+        );
+        assert_eq!(
+            error("[[load]]\naddress = 0x100000\n"),
+            "line 2: address 1048576 is outside of memory"
+        );
+        assert_eq!(
+            error("[registers]\nip = 0x10000"),
+            "line 2: 65536 does not fit into `ip`"
+        );
+        assert_eq!(
+            error("[[load]]\naddress = 1"),
+            "end of file: `[[load]]` without a `file` or `bytes`"
+        );
+    }
+}
+
+mod tui {
+    use crate::{exec::State, format::Style, tui::Viewer};
+
+    // mov cx, 3; mov bx, 64; mov [bx], cx; add ax, bx; mov al, 65; mov sp, 256
+    const PROGRAM: [u8; 15] = [
+        0xB9, 0x03, 0x00, 0xBB, 0x40, 0x00, 0x89, 0x0F, 0x01, 0xD8, 0xB0, 0x41, 0xBC, 0x00, 0x01,
+    ];
+
+    #[test]
+    fn highlights_changes_of_the_last_command() {
+        let mut state = State::default();
+        state.load_bytes(&PROGRAM);
+        let mut viewer = Viewer::new(&state, Style::default());
+        viewer.advance(&mut state, Some(3));
+        viewer.show_memory(0x47);
+        let frame = viewer.render(&state);
+
+        assert!(frame.contains("  0003  mov BX, 64"), "{frame}");
+        assert!(
+            frame.contains("\x1B[7m> 0008  add AX, BX\x1B[0m"),
+            "{frame}"
+        );
+        assert!(frame.contains("\x1B[1m\x1B[33mBX 0040\x1B[0m"), "{frame}");
+        assert!(frame.contains("  DX 0000"), "{frame}");
+        assert!(
+            frame.contains("\n0040  \x1B[1m\x1B[33m03\x1B[0m 00 00"),
+            "{frame}"
+        );
+        assert!(frame.ends_with("running\n"), "{frame}");
+
+        // only the add changes anything now
+        viewer.advance(&mut state, Some(1));
+        let frame = viewer.render(&state);
+        assert!(frame.contains("\x1B[1m\x1B[33mAX 0040\x1B[0m"), "{frame}");
+        assert!(frame.contains("BX 0040  "), "{frame}");
+        assert!(frame.contains("\n0040  03 00 00"), "{frame}");
+    }
+
+    #[test]
+    fn runs_to_the_end() {
+        let mut state = State::default();
+        state.load_bytes(&PROGRAM);
+        let mut viewer = Viewer::new(&state, Style::default());
+        viewer.advance(&mut state, None);
+        let frame = viewer.render(&state);
+
+        // the last three instructions and no current one
+        assert!(frame.contains("  0008  add AX, BX"), "{frame}");
+        assert!(frame.contains("  000c  mov SP, 256"), "{frame}");
+        assert!(!frame.contains("0006  mov"), "{frame}");
+        assert!(!frame.contains("> "), "{frame}");
+        assert!(frame.contains("SP+0  0100: 0000"), "{frame}");
+        assert!(frame.ends_with("finished\n"), "{frame}");
+    }
+}
+
+mod formatting {
+    use crate::{
+        decode,
+        format::{Case, Radix, Style, Syntax},
+    };
+
+    // mov word [bx - 2], -3; add al, [1000]; shl word [bx], cl; call far [si]; ret 500; in al, dx;
+    // jnz $-4
+    const PROGRAM: [u8; 19] = [
+        0xC7, 0x47, 0xFE, 0xFD, 0xFF, 0x02, 0x06, 0xE8, 0x03, 0xD3, 0x27, 0xFF, 0x1C, 0xC2, 0xF4,
+        0x01, 0xEC, 0x75, 0xFA,
+    ];
+
+    #[test]
+    fn default_style_is_the_display_output() {
+        assert_eq!(
+            decode::all_instructions_with(&PROGRAM, &Style::default()),
+            decode::all_instructions(&PROGRAM)
+        );
+    }
+
+    #[test]
+    fn intel_syntax() {
+        let style = Style {
+            syntax: Syntax::Intel,
+            radix: Radix::Hexadecimal,
+            ..Style::default()
+        };
+        assert_eq!(
+            decode::all_instructions_with(&PROGRAM, &style),
+            ".8086
+mov word ptr [BX - 2h], 0FFFDh
+add AL, [3E8h]
+shl word ptr [BX], CL
+call dword ptr [SI]
+ret 1F4h
+in AL, DX
+jnz $+2-6h
+"
+        );
+    }
+
+    #[test]
+    fn att_syntax() {
+        let style = Style {
+            syntax: Syntax::Att,
+            registers: Case::Lower,
+            ..Style::default()
+        };
+        assert_eq!(
+            decode::all_instructions_with(&PROGRAM, &style),
+            ".code16
+movw $-3, -2(%bx)
+add 1000, %al
+shlw %cl, (%bx)
+lcall *(%si)
+ret $500
+in (%dx), %al
+jnz .+2-6
+"
+        );
+    }
+
+    #[test]
+    fn letter_case_and_radix() {
+        let style = Style {
+            mnemonics: Case::Upper,
+            registers: Case::Lower,
+            radix: Radix::Hexadecimal,
+            ..Style::default()
+        };
+        assert_eq!(
+            decode::all_instructions_with(&PROGRAM[..9], &style),
+            "bits 16
+MOV [bx - 0x2], WORD 0xfffd
+ADD al, [0x3e8]
+"
+        );
+    }
+}
+
+mod self_modification {
+    use crate::{
+        tests::run_program,
+        watch::{CodeKind, CodeWrite},
+        RegisterByte::*,
+    };
+
+    #[test]
+    fn patched_jump_offset_takes_effect() {
+        // mov byte [6], 2; jcxz $+2+0; mov al, 1; mov bl, 7
+        let state = run_program(&[
+            0xC6, 0x06, 0x06, 0x00, 0x02, 0xE3, 0x00, 0xB0, 0x01, 0xB3, 0x07,
+        ]);
+        // the patched jump skips over `mov al, 1`
+        assert_eq!(state.registers.byte(AL), 0);
+        assert_eq!(state.registers.byte(BL), 7);
+        assert_eq!(
+            state.code_writes,
+            [CodeWrite {
+                instruction: 0,
+                address: 6,
+                kind: CodeKind::Pending,
+            }]
+        );
+        assert_eq!(
+            state.code_writes[0].to_string(),
+            "0000: wrote into code at 0x0006, which is pending in the prefetch queue"
+        );
+    }
+
+    #[test]
+    fn writes_into_executed_code() {
+        // mov cx, 2; l: add byte [9], 1; add al, 0; loop l
+        // every pass bumps the immediate of `add al`, which already ran in the previous one
+        let state = run_program(&[
+            0xB9, 0x02, 0x00, 0x80, 0x06, 0x09, 0x00, 0x01, 0x04, 0x00, 0xE2, 0xF7,
+        ]);
+        assert_eq!(state.registers.byte(AL), 1 + 2);
+        let kinds: Vec<_> = state.code_writes.iter().map(|write| write.kind).collect();
+        assert_eq!(kinds, [CodeKind::Pending, CodeKind::Executed]);
+        assert!(state.code_writes.iter().all(|write| write.address == 9));
+    }
+
+    #[test]
+    fn data_writes_are_not_code_writes() {
+        // mov word [1000], 5; mov al, [1000]
+        let state = run_program(&[0xC7, 0x06, 0xE8, 0x03, 0x05, 0x00, 0xA0, 0xE8, 0x03]);
+        assert_eq!(state.registers.byte(AL), 5);
+        assert!(state.code_writes.is_empty());
+    }
+}
+
 mod simulation {
-    // #[test]
-    // fn listing_43_immediate_movs() {}
+    use std::path::PathBuf;
+
+    use super::LISTING_DIRECTORY;
+    use crate::{exec::State, RegisterWord::*, SegmentRegister::*};
+
+    /// Register values the reference trace expects once a single instruction executed.
+    #[derive(Debug, Default)]
+    struct Step {
+        instruction: String,
+        registers: Vec<(String, u16)>,
+        flags: Option<String>,
+    }
+
+    /// Expected execution of a listing, from the reference `.txt` trace and `.data` memory dump.
+    #[derive(Debug, Default)]
+    struct Golden {
+        steps: Vec<Step>,
+        /// The final registers, where the ones left out are expected to be zero.
+        registers: Vec<(String, u16)>,
+        flags: String,
+        memory: Option<Vec<u8>>,
+    }
+
+    fn parse_hex(value: &str) -> u16 {
+        u16::from_str_radix(value.trim_start_matches("0x"), 16)
+            .unwrap_or_else(|_| panic!("invalid value `{value}` in trace"))
+    }
+
+    /// Parses a trace as printed by the reference simulator, with lines like
+    /// `add bx, 2 ; bx:0x1->0x3 ip:0x3->0x6 flags:->P` followed by the final registers.
+    fn parse_trace(trace: &str) -> Golden {
+        let mut golden = Golden::default();
+        let mut lines = trace.lines();
+        for line in lines.by_ref() {
+            if line.starts_with("Final registers:") {
+                break;
+            }
+            let Some((instruction, effects)) = line.split_once(';') else {
+                continue;
+            };
+            let mut step = Step {
+                instruction: instruction.trim().to_owned(),
+                ..Step::default()
+            };
+            // the clocks of the cycle estimating listings come in between the changes
+            for effect in effects.split_whitespace() {
+                let Some((name, (_, new))) = effect
+                    .split_once(':')
+                    .and_then(|(name, change)| Some((name, change.split_once("->")?)))
+                else {
+                    continue;
+                };
+                match name {
+                    "flags" => step.flags = Some(new.to_owned()),
+                    _ => step.registers.push((name.to_owned(), parse_hex(new))),
+                }
+            }
+            golden.steps.push(step);
+        }
+        for line in lines {
+            let Some((name, value)) = line.trim().split_once(": ") else {
+                break;
+            };
+            match name {
+                "flags" => golden.flags = value.to_owned(),
+                _ => {
+                    let value = value.split_whitespace().next().unwrap_or_default();
+                    golden.registers.push((name.to_owned(), parse_hex(value)));
+                }
+            }
+        }
+        golden
+    }
+
+    fn register(state: &State, name: &str) -> Option<u16> {
+        let registers = &state.registers;
+        Some(match name {
+            "ax" => registers[AX],
+            "bx" => registers[BX],
+            "cx" => registers[CX],
+            "dx" => registers[DX],
+            "sp" => registers[SP],
+            "bp" => registers[BP],
+            "si" => registers[SI],
+            "di" => registers[DI],
+            "es" => registers[ES],
+            "cs" => registers[CS],
+            "ss" => registers[SS],
+            "ds" => registers[DS],
+            "ip" => state.ip(),
+            _ => return None,
+        })
+    }
+
+    /// Compares the `registers` and `flags` of `state`, regardless of the order of the flags.
+    fn compare(
+        state: &State,
+        registers: &[(String, u16)],
+        flags: Option<&str>,
+    ) -> Result<(), String> {
+        for (name, expected) in registers {
+            let actual =
+                register(state, name).ok_or_else(|| format!("unknown register `{name}`"))?;
+            if actual != *expected {
+                return Err(format!("{name} is {actual:#x}, expected {expected:#x}"));
+            }
+        }
+        if let Some(expected) = flags {
+            let sorted = |flags: &str| {
+                let mut flags: Vec<_> = flags.chars().collect();
+                flags.sort_unstable();
+                flags
+            };
+            let actual = state.registers.flags_string();
+            if sorted(&actual) != sorted(expected) {
+                return Err(format!("flags are `{actual}`, expected `{expected}`"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `program` along `golden`, describing the first step that doesn't match.
+    fn check(program: &[u8], golden: &Golden) -> Result<(), String> {
+        let mut state = State::default();
+        state.load_bytes(program);
+        for (index, step) in golden.steps.iter().enumerate() {
+            let describe = |error| format!("step {} `{}`: {error}", index + 1, step.instruction);
+            if !state.is_running() {
+                return Err(describe("the program already ended".to_owned()));
+            }
+            state.step();
+            if let Some(stop) = state.stop {
+                return Err(describe(format!("simulation stopped: {stop}")));
+            }
+            compare(&state, &step.registers, step.flags.as_deref()).map_err(describe)?;
+        }
+        if state.is_running() {
+            return Err(format!(
+                "the program runs on past the {} steps of the trace",
+                golden.steps.len()
+            ));
+        }
+
+        let names = [
+            "ax", "bx", "cx", "dx", "sp", "bp", "si", "di", "es", "cs", "ss", "ds",
+        ];
+        let mut registers: Vec<_> = names
+            .iter()
+            .map(|name| (name.to_string(), 0))
+            .filter(|(name, _)| golden.registers.iter().all(|(listed, _)| listed != name))
+            .collect();
+        registers.extend(golden.registers.iter().cloned());
+        compare(&state, &registers, Some(&golden.flags))
+            .map_err(|error| format!("final registers: {error}"))?;
+
+        if let Some(memory) = &golden.memory {
+            let mismatch = memory.iter().zip(&state.memory).position(|(a, b)| a != b);
+            if let Some(address) = mismatch {
+                return Err(format!(
+                    "memory at {address:#06x} is {:#04x}, expected {:#04x}",
+                    state.memory[address], memory[address]
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Every listing with a reference trace, given by the paths of the trace and the program.
+    fn golden_listings() -> Vec<(PathBuf, PathBuf)> {
+        let mut listings: Vec<_> = std::fs::read_dir(LISTING_DIRECTORY)
+            .expect("the course listings are checked out")
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.starts_with("listing_00") && name.ends_with(".txt")
+            })
+            .map(|trace| (trace.clone(), trace.with_extension("")))
+            .filter(|(_, program)| program.exists())
+            .collect();
+        listings.sort();
+        listings
+    }
+
+    #[test]
+    fn course_listings() {
+        let listings = golden_listings();
+        assert!(!listings.is_empty(), "no reference traces found");
+
+        let mut failures = Vec::new();
+        for (trace, program) in listings {
+            let mut golden = parse_trace(&std::fs::read_to_string(&trace).unwrap());
+            golden.memory = std::fs::read(program.with_extension("data")).ok();
+            if let Err(error) = check(&std::fs::read(&program).unwrap(), &golden) {
+                failures.push(format!("{}: {error}", program.display()));
+            }
+        }
+        assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    }
 
-    // #[test]
-    // fn listing_44_register_movs() {}
+    // mov ax, 1; mov bx, 2; add ax, bx; sub bx, bx
+    const PROGRAM: [u8; 10] = [0xB8, 0x01, 0x00, 0xBB, 0x02, 0x00, 0x01, 0xD8, 0x29, 0xDB];
+    const TRACE: &str = "--- test\\listing_inline execution ---
+mov ax, 1 ; ax:0x0->0x1 ip:0x0->0x3
+mov bx, 2 ; bx:0x0->0x2 ip:0x3->0x6
+add ax, bx ; ax:0x1->0x3 ip:0x6->0x8 flags:->P
+sub bx, bx ; bx:0x2->0x0 ip:0x8->0xa flags:P->PZ
+
+Final registers:
+      ax: 0x0003 (3)
+      ip: 0x000a (10)
+   flags: PZ
+";
+
+    #[test]
+    fn follows_a_reference_trace() {
+        let golden = parse_trace(TRACE);
+        assert_eq!(golden.steps.len(), 4);
+        assert_eq!(golden.steps[3].flags.as_deref(), Some("PZ"));
+        assert_eq!(check(&PROGRAM, &golden), Ok(()));
+    }
+
+    #[test]
+    fn reports_the_first_mismatching_step() {
+        let golden = parse_trace(&TRACE.replace("ax:0x1->0x3", "ax:0x1->0x4"));
+        assert_eq!(
+            check(&PROGRAM, &golden),
+            Err("step 3 `add ax, bx`: ax is 0x3, expected 0x4".to_owned())
+        );
+
+        let mut golden = parse_trace(&TRACE.replace("flags:P->PZ", "flags:P->Z"));
+        assert_eq!(
+            check(&PROGRAM, &golden),
+            Err("step 4 `sub bx, bx`: flags are `PZ`, expected `Z`".to_owned())
+        );
+
+        golden = parse_trace(TRACE);
+        golden.memory = Some(vec![0xB8, 0x01, 0x00, 0xBB, 0x03]);
+        assert_eq!(
+            check(&PROGRAM, &golden),
+            Err("memory at 0x0004 is 0x02, expected 0x03".to_owned())
+        );
+    }
 }
 
 mod challenge {
-    // use super::process_file_listing;
-
-    // #[test]
-    // fn listing_40() {
-    //     process_file_listing("listing_0040_challenge_movs");
-    // }
-
-    // #[test]
-    // fn listing_42() {
-    //     process_file_listing("listing_0042_completionist_decode");
-    // }
-
-    // #[test]
-    // fn listing_45() {
-    //     process_file_listing("listing_0045_challenge_register_movs");
-    // }
+    use super::{assemble, process_file_listing};
+    use crate::{decode, exec::StopReason, tests::run_program, RegisterWord::*};
+
+    #[test]
+    fn listing_40() {
+        process_file_listing("listing_0040_challenge_movs");
+    }
+
+    #[test]
+    fn listing_42() {
+        process_file_listing("listing_0042_completionist_decode");
+    }
+
+    #[test]
+    fn listing_45() {
+        process_file_listing("listing_0045_challenge_register_movs");
+    }
+
+    /// Assembles `source`, then checks that disassembling and reassembling it gives the same bytes.
+    fn round_trip(source: &str, name: &str) {
+        let (program, _) = assemble(format!("bits 16\n{source}"), name);
+        let disassembly = decode::all_instructions(&program);
+        let (assembled, _) = assemble(disassembly.clone(), &format!("{name}_again"));
+        assert_eq!(program, assembled, "{disassembly}");
+    }
+
+    #[test]
+    fn displacements_and_direct_addresses_round_trip() {
+        round_trip(
+            "mov ax, [bx + di - 37]
+            mov [si - 300], cx
+            mov dx, [bx - 32]
+            mov [bp + di], byte 7
+            mov [di + 901], word 347
+            mov bp, [5]
+            mov ax, [2555]
+            mov [15], al
+            mov al, cs:[bx + si]
+            cmp cx, es:[4384]
+            sbb word cs:[bx + si - 4332], 10328",
+            "challenge_movs",
+        );
+    }
+
+    #[test]
+    fn segment_moves_round_trip() {
+        round_trip(
+            "mov ss, ax
+            mov ds, bx
+            mov es, cx
+            mov sp, ss
+            mov bp, ds
+            mov [bx + si + 59], es
+            push cs
+            pop ds",
+            "challenge_segment_movs",
+        );
+    }
+
+    #[test]
+    fn completionist_forms_round_trip() {
+        round_trip(
+            "push word [bp + si]
+            pop word [bx + di - 3000]
+            push cx
+            pop sp
+            xchg ax, [bp - 1000]
+            xchg ax, dx
+            xchg cl, ah
+            xlat
+            lea bx, [bp - 50]
+            lds sp, [bp - 1003]
+            les di, [bx + si - 7]
+            lahf
+            sahf
+            pushf
+            popf
+            inc dh
+            dec word [9349]
+            aaa
+            das
+            mul word [bp]
+            imul byte [bx]
+            div byte [bx + si + 2990]
+            idiv si
+            aam
+            aad
+            cbw
+            cwd
+            rep movsb
+            rep stosw
+            repne scasb
+            lodsw
+            call [bp - 100]
+            call sp
+            jmp [12]
+            call far [bp - 108]
+            jmp far [di]
+            call 123:456
+            jmp 21862:30600
+            ret -7
+            ret
+            retf 17556
+            retf
+            int 13
+            int3
+            into
+            iret
+            clc
+            cmc
+            stc
+            cld
+            std
+            cli
+            sti
+            hlt
+            wait
+            lock xchg [100], al",
+            "completionist",
+        );
+    }
+
+    #[test]
+    fn decode_challenge_forms() {
+        let disassembly = decode::all_instructions(&[
+            0x8B, 0x41, 0xDB, 0x89, 0x8C, 0xD4, 0xFE, 0xA1, 0xFB, 0x09, 0x2E, 0x8A, 0x00, 0x8E,
+            0xD0, 0xF3, 0xA4, 0xE8, 0x9C, 0xFF, 0xCA, 0x94, 0x44,
+        ]);
+        assert_eq!(
+            disassembly,
+            "bits 16
+mov AX, [BX + DI - 37]
+mov [SI - 300], CX
+mov AX, [2555]
+mov AL, CS:[BX + SI]
+mov SS, AX
+rep movsb
+call near $+3-100
+retf 17556
+"
+        );
+    }
+
+    #[test]
+    fn unsupported_instructions_stop_the_simulation() {
+        // inc ax; dec bx; push ax
+        let mut state = crate::exec::State::default();
+        state.load_bytes(&[0x40, 0x4B, 0x50]);
+        let result = crate::exec::all_instructions(&mut state);
+        assert_eq!(state.registers[AX], 1);
+        assert_eq!(state.registers[BX], 0xFFFF);
+        let Err(StopReason::Unsupported {
+            address,
+            instruction,
+        }) = result
+        else {
+            panic!("expected an unsupported instruction, got {result:?}");
+        };
+        assert_eq!(address, 2);
+        assert_eq!(instruction.to_string(), "push AX");
+    }
+
+    #[test]
+    fn inc_and_dec_leave_carry_alone() {
+        // mov ax, 0xFFFF; add ax, 1; dec ax; inc word [bx]
+        let state = run_program(&[0xB8, 0xFF, 0xFF, 0x05, 0x01, 0x00, 0x48, 0xFF, 0x07]);
+        assert_eq!(state.registers[AX], 0xFFFF);
+        assert!(state.registers.flag_carry);
+        assert!(state.registers.flag_sign);
+        assert_eq!(&state.memory[0..2], &[0xB9, 0xFF]);
+    }
 }
@@ -0,0 +1,280 @@
+//! Full screen view of a simulation for terminals understanding ANSI escape codes, showing the
+//! code around the instruction pointer, the registers, flags, stack and a window into memory.
+//!
+//! The [`Viewer`] only renders frames, reading commands and drawing them is up to the caller.
+
+use std::{
+    collections::VecDeque,
+    fmt::{Debug, Write},
+    ops::Index,
+};
+
+use crate::{
+    decode,
+    exec::{physical_address, Registers, State, MEMORY_SIZE},
+    format::Style,
+    RegisterWord, SegmentRegister,
+};
+
+/// Marks values changed by the last command.
+const CHANGED: &str = "\x1B[1m\x1B[33m";
+/// Marks the instruction about to execute.
+const CURRENT: &str = "\x1B[7m";
+const RESET: &str = "\x1B[0m";
+/// Clears the terminal and moves the cursor to its top left corner.
+pub const CLEAR: &str = "\x1B[2J\x1B[H";
+
+/// Executed instructions shown above the current one.
+const HISTORY: usize = 3;
+/// Instructions shown from the current one on.
+const UPCOMING: usize = 9;
+/// Words shown from the top of the stack on.
+const STACK_WORDS: usize = 6;
+pub const MEMORY_ROWS: usize = 8;
+const BYTES_PER_ROW: usize = 16;
+/// Width of the code column, the rest of the panels go to its right.
+const CODE_WIDTH: usize = 44;
+
+pub struct Viewer {
+    pub style: Style,
+    /// First address of the memory window, a multiple of 16.
+    memory_start: usize,
+    /// Addresses of the instructions executed last, the most recent one at the back.
+    history: VecDeque<usize>,
+    /// Registers and memory before the last command, to highlight what it changed.
+    registers_prior: Registers,
+    memory_prior: Box<[u8]>,
+}
+
+impl Viewer {
+    pub fn new(state: &State, style: Style) -> Self {
+        Self {
+            style,
+            memory_start: 0,
+            history: VecDeque::with_capacity(HISTORY + 1),
+            registers_prior: state.registers.clone(),
+            memory_prior: state.memory.clone(),
+        }
+    }
+
+    /// Executes up to `steps` instructions, or until the simulation stops if `None`. The next
+    /// frame highlights everything they changed together.
+    pub fn advance(&mut self, state: &mut State, steps: Option<usize>) {
+        self.registers_prior = state.registers.clone();
+        self.memory_prior.copy_from_slice(&state.memory);
+        let mut remaining = steps.unwrap_or(usize::MAX);
+        while remaining > 0 && state.is_running() {
+            let address = state.instruction_pointer;
+            state.step();
+            // instructions the simulator refuses leave the instruction pointer where it was
+            let executed = state.instruction_pointer != address || state.stop.is_none();
+            if executed {
+                if self.history.len() == HISTORY {
+                    self.history.pop_front();
+                }
+                self.history.push_back(address);
+            }
+            remaining -= 1;
+        }
+    }
+
+    /// Moves the memory window to show `address`.
+    pub fn show_memory(&mut self, address: usize) {
+        self.memory_start = address % MEMORY_SIZE / BYTES_PER_ROW * BYTES_PER_ROW;
+    }
+
+    /// Moves the memory window by `rows` rows of 16 bytes, wrapping around the address space.
+    pub fn scroll_memory(&mut self, rows: isize) {
+        let bytes = rows * BYTES_PER_ROW as isize;
+        self.memory_start = self.memory_start.wrapping_add_signed(bytes) % MEMORY_SIZE;
+    }
+
+    /// Renders the whole screen for `state`, without clearing the terminal first.
+    pub fn render(&self, state: &State) -> String {
+        let code = self.code(state);
+        let mut side = self.registers(&state.registers, state.ip());
+        side.push(String::new());
+        side.push(self.flags(&state.registers));
+        side.push(String::new());
+        side.extend(self.stack(state));
+
+        let mut frame = String::new();
+        let mut line = |text: std::fmt::Arguments| {
+            frame
+                .write_fmt(format_args!("{text}\n"))
+                .expect("can write into the frame string");
+        };
+        for row in 0..code.len().max(side.len()) {
+            let left = code.get(row).map_or("", String::as_str);
+            let right = side.get(row).map_or("", String::as_str);
+            let padding = CODE_WIDTH.saturating_sub(visible_width(left));
+            line(format_args!("{left}{:padding$}{right}", ""));
+        }
+        line(format_args!(""));
+        for text in self.memory(state) {
+            line(format_args!("{text}"));
+        }
+        line(format_args!(""));
+        line(format_args!("{}", status(state)));
+        frame
+    }
+
+    fn code(&self, state: &State) -> Vec<String> {
+        let mut lines = vec!["Code".to_owned()];
+        let disassemble = |address: usize| {
+            let (length, instruction) = decode::single_instruction(&mut &state.memory[address..]);
+            (
+                length,
+                format!("{address:04x}  {}", self.style.instruction(&instruction)),
+            )
+        };
+        for &address in &self.history {
+            lines.push(format!("  {}", disassemble(address).1));
+        }
+        let mut address = state.instruction_pointer;
+        for index in 0..UPCOMING {
+            if !state.is_code(address) {
+                break;
+            }
+            let (length, text) = disassemble(address);
+            if index == 0 {
+                lines.push(format!("{CURRENT}> {text}{RESET}"));
+            } else {
+                lines.push(format!("  {text}"));
+            }
+            address += length;
+        }
+        lines
+    }
+
+    fn registers(&self, registers: &Registers, instruction_pointer: u16) -> Vec<String> {
+        use RegisterWord::*;
+        use SegmentRegister::*;
+        let prior = &self.registers_prior;
+        let general = register_column(&[AX, BX, CX, DX], registers, prior);
+        let pointers = register_column(&[SP, BP, SI, DI], registers, prior);
+        let segments = register_column(&[ES, CS, SS, DS], registers, prior);
+        let mut lines = vec!["Registers".to_owned()];
+        for ((first, second), third) in general.iter().zip(&pointers).zip(&segments) {
+            lines.push(format!("{first}  {second}  {third}"));
+        }
+        lines.push(format!("IP {instruction_pointer:04x}"));
+        lines
+    }
+
+    fn flags(&self, registers: &Registers) -> String {
+        let prior = &self.registers_prior;
+        let flags = [
+            ('C', registers.flag_carry, prior.flag_carry),
+            ('P', registers.flag_parity, prior.flag_parity),
+            (
+                'A',
+                registers.flag_auxiliary_carry,
+                prior.flag_auxiliary_carry,
+            ),
+            ('Z', registers.flag_zero, prior.flag_zero),
+            ('S', registers.flag_sign, prior.flag_sign),
+            ('O', registers.flag_overflow, prior.flag_overflow),
+        ];
+        let mut line = "Flags ".to_owned();
+        for (name, set, before) in flags {
+            let shown = if set { name } else { '.' };
+            if set == before {
+                write!(line, " {shown}")
+            } else {
+                write!(line, " {CHANGED}{shown}{RESET}")
+            }
+            .expect("can write into the flags string");
+        }
+        line
+    }
+
+    fn stack(&self, state: &State) -> Vec<String> {
+        let stack_segment = state.registers[SegmentRegister::SS];
+        let stack_pointer = state.registers[RegisterWord::SP];
+        let mut lines = vec!["Stack".to_owned()];
+        for index in 0..STACK_WORDS {
+            let offset = stack_pointer.wrapping_add(2 * index as u16);
+            let address = physical_address(stack_segment, offset);
+            let bytes = [address, (address + 1) % MEMORY_SIZE];
+            let value = u16::from_le_bytes(bytes.map(|i| state.memory[i]));
+            let changed = bytes
+                .iter()
+                .any(|&i| state.memory[i] != self.memory_prior[i]);
+            let text = format!("SP+{:<2} {address:04x}: {value:04x}", 2 * index);
+            lines.push(if changed {
+                format!("{CHANGED}{text}{RESET}")
+            } else {
+                text
+            });
+        }
+        lines
+    }
+
+    fn memory(&self, state: &State) -> Vec<String> {
+        let mut lines = vec!["Memory".to_owned()];
+        for row in 0..MEMORY_ROWS {
+            let start = (self.memory_start + row * BYTES_PER_ROW) % MEMORY_SIZE;
+            let mut hex = String::new();
+            let mut text = String::new();
+            for i in start..start + BYTES_PER_ROW {
+                let byte = state.memory[i];
+                if byte == self.memory_prior[i] {
+                    write!(hex, " {byte:02x}")
+                } else {
+                    write!(hex, " {CHANGED}{byte:02x}{RESET}")
+                }
+                .expect("can write into the memory string");
+                text.push(if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                });
+            }
+            lines.push(format!("{start:04x} {hex}  |{text}|"));
+        }
+        lines
+    }
+}
+
+/// Why the simulation is not running any more, if it isn't.
+fn status(state: &State) -> String {
+    match state.stop {
+        Some(stop) => format!("stopped: {stop}"),
+        None if state.is_running() => "running".to_owned(),
+        None => "finished".to_owned(),
+    }
+}
+
+/// Columns `text` takes up on the terminal, not counting escape sequences.
+fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut in_escape = false;
+    for c in text.chars() {
+        match c {
+            '\x1B' => in_escape = true,
+            'm' if in_escape => in_escape = false,
+            _ if !in_escape => width += 1,
+            _ => (),
+        }
+    }
+    width
+}
+
+/// Formats each of `list` with its value, highlighting those that changed since `prior`.
+fn register_column<R>(list: &[R], registers: &Registers, prior: &Registers) -> Vec<String>
+where
+    R: Copy + Debug,
+    Registers: Index<R, Output = u16>,
+{
+    list.iter()
+        .map(|&register| {
+            let (value, before) = (registers[register], prior[register]);
+            if value == before {
+                format!("{register:?} {value:04x}")
+            } else {
+                format!("{CHANGED}{register:?} {value:04x}{RESET}")
+            }
+        })
+        .collect()
+}
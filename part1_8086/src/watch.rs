@@ -0,0 +1,135 @@
+//! Memory watchpoints, recording or stopping on accesses to ranges of memory, and detection of
+//! programs writing into their own code.
+
+use std::ops::RangeInclusive;
+
+use crate::Width;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A single memory transfer made by an executed instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    /// Address of the instruction making the access.
+    pub instruction: usize,
+    pub kind: AccessKind,
+    pub address: usize,
+    pub width: Width,
+    /// Value stored before the access.
+    pub old: u16,
+    /// Value stored after the access, the same as `old` for reads.
+    pub new: u16,
+}
+
+impl std::fmt::Display for MemoryAccess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            instruction,
+            address,
+            width,
+            old,
+            new,
+            ..
+        } = self;
+        write!(f, "{instruction:04x}: ")?;
+        match self.kind {
+            AccessKind::Read => write!(f, "read {width} [{address:#06x}]: {old:#x}"),
+            AccessKind::Write => write!(f, "write {width} [{address:#06x}]: {old:#x} -> {new:#x}"),
+        }
+    }
+}
+
+/// What happens once a watchpoint is hit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchAction {
+    /// Append the access to [`Watchpoints::log`].
+    Log,
+    /// Log the access and stop the simulation once the instruction finishes.
+    Break,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<usize>,
+    pub reads: bool,
+    pub writes: bool,
+    pub action: WatchAction,
+}
+
+impl Watchpoint {
+    fn matches(&self, access: &MemoryAccess) -> bool {
+        let wanted = match access.kind {
+            AccessKind::Read => self.reads,
+            AccessKind::Write => self.writes,
+        };
+        let last = access.address + (access.width == Width::Word) as usize;
+        wanted && access.address <= *self.range.end() && *self.range.start() <= last
+    }
+}
+
+/// The watchpoints registered on a [`State`], along with the accesses they caught.
+///
+/// [`State`]: crate::exec::State
+#[derive(Debug, Default)]
+pub struct Watchpoints {
+    watchpoints: Vec<Watchpoint>,
+    pub log: Vec<MemoryAccess>,
+}
+
+impl Watchpoints {
+    pub fn add(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    /// Logs `access` if any watchpoint covers it, returning whether one of them breaks.
+    pub(crate) fn check(&mut self, access: MemoryAccess) -> bool {
+        let mut matching = self.watchpoints.iter().filter(|w| w.matches(&access));
+        let Some(first) = matching.next() else {
+            return false;
+        };
+        self.log.push(access);
+        first.action == WatchAction::Break || matching.any(|w| w.action == WatchAction::Break)
+    }
+}
+
+/// Why a written byte counts as code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeKind {
+    /// The byte was part of an instruction that already executed.
+    Executed,
+    /// The byte follows the writing instruction closely enough that the 8086 would already have
+    /// fetched it into its prefetch queue, where the write doesn't reach.
+    Pending,
+}
+
+/// A write of an executed instruction into code, see [`CodeKind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeWrite {
+    /// Address of the instruction making the write.
+    pub instruction: usize,
+    pub address: usize,
+    pub kind: CodeKind,
+}
+
+impl std::fmt::Display for CodeWrite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            instruction,
+            address,
+            ..
+        } = self;
+        write!(f, "{instruction:04x}: wrote into code at {address:#06x}, ")?;
+        match self.kind {
+            CodeKind::Executed => f.write_str("which already executed"),
+            CodeKind::Pending => f.write_str("which is pending in the prefetch queue"),
+        }
+    }
+}